@@ -0,0 +1,55 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+//! Latency of a low-volume logger, from logging a record to the server
+//! receiving it, with and without `inline_when_idle`:
+//!
+//! ```sh
+//! cargo bench --bench latency
+//! ```
+
+use std::{
+    io::{BufRead, BufReader},
+    net::TcpListener,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gelf_logger::Builder;
+use log::{Level, Log, Record};
+
+fn latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inline_when_idle");
+    for inline in [false, true] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let logger = Builder::new()
+            .port(port)
+            .inline_when_idle(inline)
+            .build()
+            .unwrap();
+        logger.connect().unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = BufReader::new(stream);
+        let mut line = Vec::new();
+
+        // Each record is received before logging the next one, so that the
+        // background thread is idle.
+        group.bench_function(BenchmarkId::from_parameter(inline), |b| {
+            b.iter(|| {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("something happen"))
+                        .level(Level::Error)
+                        .build(),
+                );
+                line.clear();
+                server.read_until(b'\n', &mut line).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, latency);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+//! Serialization throughput of representative records, with `serde_json` and,
+//! with the `simd` feature, `simd-json`:
+//!
+//! ```sh
+//! cargo bench --bench serialization
+//! cargo bench --bench serialization --features simd
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gelf_logger::{Builder, GelfRecord};
+use log::{kv::ToValue, Level, Record};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Request<'a> {
+    id: u64,
+    method: &'a str,
+    path: &'a str,
+    elapsed: f64,
+    headers: [(&'a str, &'a str); 2],
+}
+
+fn serialization(c: &mut Criterion) {
+    let request = Request {
+        id: 42,
+        method: "GET",
+        path: "/api/v1/users?page=2",
+        elapsed: 0.0153,
+        headers: [("user-agent", "curl/8.5.0"), ("accept", "*/*")],
+    };
+    let body = include_str!("../LICENSE");
+    let kvs = [
+        ("user", "jdoe".to_value()),
+        ("status", 200.to_value()),
+        ("ratio", 1e-7.to_value()),
+        ("cached", false.to_value()),
+        ("message", "quote \" backslash \\ é 🦀".to_value()),
+        ("request", log::kv::Value::from_serde(&request)),
+    ];
+    let kvs_with_body = [("body", body.to_value())];
+    let records = [("small", &kvs[..]), ("large_string", &kvs_with_body[..])];
+    // Serializes the records as the targets do, but drops them.
+    let logger = Builder::new().dry_run(true).build().unwrap();
+
+    let mut group = c.benchmark_group("serialization");
+    for (name, kvs) in records {
+        let record = Record::builder()
+            .args(format_args!("request served"))
+            .level(Level::Info)
+            .target("api::handler")
+            .file_static(Some(file!()))
+            .line(Some(line!()))
+            .key_values(&kvs)
+            .build();
+        let record = GelfRecord::from(&record);
+        let len = serde_json::to_vec(&record).unwrap().len();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(
+            BenchmarkId::new("serde_json", name),
+            &record,
+            |b, record| b.iter(|| serde_json::to_vec(record).unwrap()),
+        );
+        #[cfg(feature = "simd")]
+        group.bench_with_input(BenchmarkId::new("simd_json", name), &record, |b, record| {
+            b.iter(|| simd_json::to_vec(record).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("logger", name), &record, |b, record| {
+            b.iter(|| logger.log_record(record))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
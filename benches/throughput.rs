@@ -0,0 +1,60 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+//! Throughput of the TCP target depending on its number of background
+//! workers, each with its own connection. The records are counted as sent
+//! once flushed:
+//!
+//! ```sh
+//! cargo bench --bench throughput
+//! ```
+
+use std::{io, net::TcpListener, thread};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gelf_logger::Builder;
+use log::{Level, Log, Record};
+
+const RECORDS: u64 = 1_000;
+
+fn throughput(c: &mut Criterion) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // Discard what every connection receives.
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || io::copy(&mut stream, &mut io::sink()));
+        }
+    });
+
+    let mut group = c.benchmark_group("worker_count");
+    group.throughput(Throughput::Elements(RECORDS));
+    for workers in [1, 2, 4, 8] {
+        let logger = Builder::new()
+            .port(port)
+            .worker_count(workers)
+            .build()
+            .unwrap();
+        logger.connect().unwrap();
+
+        group.bench_function(BenchmarkId::from_parameter(workers), |b| {
+            b.iter(|| {
+                for _ in 0..RECORDS {
+                    logger.log(
+                        &Record::builder()
+                            .args(format_args!("something happen"))
+                            .level(Level::Error)
+                            .build(),
+                    );
+                }
+                logger.flush();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+use std::env;
+
+use gelf_logger::Builder;
+use log::{Level, Record};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    // Init logger.
+    let logger = Builder::new()
+        .parse_filters("debug")
+        .hostname(env::args().nth(1).expect("missing hostname"))
+        .port(
+            env::args()
+                .nth(2)
+                .map_or(12201, |port| port.parse().expect("invalid port")),
+        )
+        .build_async()
+        .expect("invalid configuration");
+
+    // Records are written from the current task.
+    let record = Record::builder()
+        .args(format_args!("packet received"))
+        .level(Level::Info)
+        .target(module_path!())
+        .build();
+    if let Err(err) = logger.log(&record).await {
+        eprintln!("failed to log: {err}");
+    }
+
+    // Flush underlying TCP socket.
+    logger.flush().await.expect("failed to flush");
+}
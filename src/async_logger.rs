@@ -0,0 +1,345 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{
+    future::Future,
+    io,
+    sync::{Arc, Mutex as SyncMutex, PoisonError},
+    time::Duration,
+};
+
+use log::{Log, Metadata, Record};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    runtime::Handle,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    time,
+};
+
+use crate::{Error, GelfLogger, GelfRecord, TcpTarget};
+
+/// A logger writing the records to a TCP target from the calling task, for
+/// services running on a Tokio runtime.
+///
+/// It is built with [`Builder::build_async`](crate::Builder::build_async) and
+/// formats the records like a [`GelfLogger`]. The connection is established on
+/// first use and reestablished after a write error. TLS connections use
+/// `native-tls`, which the `tokio` feature enables and which takes precedence
+/// over the `rustls` feature.
+#[derive(Debug)]
+pub struct AsyncGelfLogger {
+    logger: GelfLogger,
+    target: TcpTarget,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl AsyncGelfLogger {
+    pub(crate) fn new(logger: GelfLogger, target: TcpTarget) -> Self {
+        Self {
+            logger,
+            target,
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Format and send the record, unless it is filtered out.
+    pub fn log(&self, record: &Record<'_>) -> impl Future<Output = Result<(), Error>> + Send + '_ {
+        // The record is formatted before the first await point since it can't
+        // be held across it.
+        let data = if self.logger.matches(record) {
+            self.logger
+                .prepare(record, &self.logger.writer)
+                .map(|record| self.logger.encode(&record))
+        } else {
+            Ok(None)
+        };
+        async move {
+            match data? {
+                Some(data) => self.write(data).await,
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Serialize and send an already built [`GelfRecord`].
+    ///
+    /// The record is sent as is: neither the filter nor the additional fields
+    /// set up on the [`Builder`](crate::Builder) are applied.
+    pub async fn log_record(&self, record: &GelfRecord<'_>) -> Result<(), Error> {
+        match self.logger.encode(record) {
+            Some(data) => self.write(data).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Flush the connection, if established.
+    pub async fn flush(&self) -> Result<(), Error> {
+        if let Some(conn) = &mut *self.conn.lock().await {
+            with_timeout(self.target.write_timeout, conn.flush()).await?;
+        }
+        Ok(())
+    }
+
+    /// Create a [`Log`] implementation forwarding the records of the `log`
+    /// macros to this logger.
+    pub fn bridge(self: Arc<Self>) -> LogBridge {
+        let (tx, rx) = mpsc::unbounded_channel();
+        LogBridge {
+            logger: self,
+            tx,
+            rx: SyncMutex::new(Some(rx)),
+        }
+    }
+
+    async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        let data = self.target.framing.frame(data);
+        let mut conn = self.conn.lock().await;
+        let conn_ref = match &mut *conn {
+            Some(conn_ref) => conn_ref,
+            None => conn.insert(Connection::new(&self.target).await?),
+        };
+        let result = with_timeout(self.target.write_timeout, conn_ref.write_all(&data)).await;
+        if result.is_err() {
+            *conn = None;
+        }
+        Ok(result?)
+    }
+}
+
+/// A [`Log`] implementation forwarding the records to an [`AsyncGelfLogger`],
+/// created with [`AsyncGelfLogger::bridge`].
+///
+/// Records are formatted on the calling thread and queued to a task spawned on
+/// the Tokio runtime the first time a record is logged from within one. They
+/// are buffered until then. Flushing doesn't wait for the queued records to be
+/// written.
+#[derive(Debug)]
+pub struct LogBridge {
+    logger: Arc<AsyncGelfLogger>,
+    tx: UnboundedSender<Message>,
+    rx: SyncMutex<Option<UnboundedReceiver<Message>>>,
+}
+
+/// A message queued to the task of a [`LogBridge`].
+#[derive(Debug)]
+enum Message {
+    Data(Vec<u8>),
+    Flush,
+}
+
+impl LogBridge {
+    /// Initializes the global logger with this bridge.
+    ///
+    /// # Errors
+    ///
+    /// This function will fail if it is called more than once, or if another
+    /// library has already initialized a global logger.
+    pub fn try_init(self) -> Result<(), Error> {
+        let max_level = self.logger.logger.filter();
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    /// Send the message, spawning the task writing them if a runtime is now
+    /// available.
+    fn send(&self, message: Message) {
+        let _ = self.tx.send(message);
+
+        let Ok(handle) = Handle::try_current() else {
+            return;
+        };
+        let Some(mut rx) = self
+            .rx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        else {
+            return;
+        };
+        let logger = self.logger.clone();
+        handle.spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let _ = match message {
+                    Message::Data(data) => logger.write(data).await,
+                    Message::Flush => logger.flush().await,
+                };
+            }
+        });
+    }
+}
+
+impl Log for LogBridge {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.logger.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.logger.logger.matches(record) {
+            return;
+        }
+        if let Some(data) = self
+            .logger
+            .logger
+            .prepare(record, &self.logger.logger.writer)
+            .ok()
+            .and_then(|record| self.logger.logger.encode(&record))
+        {
+            self.send(Message::Data(data));
+        }
+    }
+
+    fn flush(&self) {
+        self.send(Message::Flush);
+    }
+}
+
+/// A connection to the TCP target.
+#[derive(Debug)]
+enum Connection {
+    Raw(TcpStream),
+    Tls(Box<tokio_native_tls::TlsStream<TcpStream>>),
+}
+
+impl Connection {
+    async fn new(target: &TcpTarget) -> Result<Self, Error> {
+        let stream = with_timeout(
+            target.connect_timeout,
+            TcpStream::connect((target.hostname.as_str(), target.port)),
+        )
+        .await?;
+        if !target.tls {
+            return Ok(Self::Raw(stream));
+        }
+
+        let connector =
+            tokio_native_tls::native_tls::TlsConnector::new().map_err(io::Error::other)?;
+        let stream = tokio_native_tls::TlsConnector::from(connector)
+            .connect(&target.hostname, stream)
+            .await
+            .map_err(io::Error::other)?;
+        Ok(Self::Tls(Box::new(stream)))
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Connection::Raw(stream) => stream.write_all(data).await,
+            Connection::Tls(stream) => stream.write_all(data).await,
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), io::Error> {
+        match self {
+            Connection::Raw(stream) => stream.flush().await,
+            Connection::Tls(stream) => stream.flush().await,
+        }
+    }
+}
+
+/// Run the I/O operation, failing with [`io::ErrorKind::TimedOut`] once the
+/// timeout, if any, is reached.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    op: impl Future<Output = Result<T, io::Error>>,
+) -> Result<T, io::Error> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, op)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+        None => op.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{mpsc, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use log::{Level, Record};
+    use serde_json::Value;
+
+    use crate::Builder;
+
+    #[tokio::test]
+    async fn log_and_flush() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            BufReader::new(stream)
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(&line.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let logger = Builder::new().port(port).build_async().unwrap();
+        for message in ["first", "second"] {
+            logger
+                .log(
+                    &Record::builder()
+                        .args(format_args!("{message}"))
+                        .level(Level::Error)
+                        .build(),
+                )
+                .await
+                .unwrap();
+        }
+        logger
+            .log(&Record::builder().args(format_args!("filtered")).build())
+            .await
+            .unwrap();
+        logger.flush().await.unwrap();
+        drop(logger);
+
+        let records = server.join().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["short_message"], "first");
+        assert_eq!(records[1]["short_message"], "second");
+    }
+
+    #[tokio::test]
+    async fn bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            for line in BufReader::new(stream).lines() {
+                tx.send(serde_json::from_str::<Value>(&line.unwrap()).unwrap())
+                    .unwrap();
+            }
+        });
+
+        let logger = Builder::new()
+            .parse_filters("info")
+            .port(port)
+            .build_async()
+            .unwrap();
+        Arc::new(logger).bridge().try_init().unwrap();
+        log::info!("bridged");
+
+        // Let the spawned task write the record.
+        let record = loop {
+            if let Ok(record) = rx.try_recv() {
+                break record;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(record["short_message"], "bridged");
+    }
+
+    #[test]
+    fn requires_tcp() {
+        assert!(Builder::new().build_async().is_err());
+    }
+}
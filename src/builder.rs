@@ -2,15 +2,39 @@
 // license that can be found in the LICENSE file.
 // Copyright 2024 The gelf_logger Authors. All rights reserved.
 
-use std::{env, time::Duration};
+use std::{
+    env,
+    fmt::Write,
+    fs, io, iter,
+    net::SocketAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
 
 use env_filter::Builder as FilterBuilder;
 use log::LevelFilter;
+use serde::Serialize;
+use socket2::Socket;
 
+#[cfg(feature = "tokio")]
+use crate::AsyncGelfLogger;
+#[cfg(feature = "http")]
+use crate::HttpTarget;
 use crate::{
     error::Error,
-    logger::{GelfLogger, Target, TcpTarget, Writer},
-    record::flatten,
+    level::{GelfLevel, LevelNameStyle},
+    logger::{
+        ConnectionFactory, Framing, FullBufferPolicy, GelfHandle, GelfLogger, Target, TcpTarget,
+        WriteTimeoutPolicy, Writer,
+    },
+    memory::{MemoryHandle, Recent},
+    record::{
+        flatten_with, CollisionPolicy, DurationUnit, LeafOptions, NonFiniteFloatPolicy,
+        NullFieldPolicy, RecordOptions,
+    },
+    spill::SpillFile,
     Map, Value,
 };
 
@@ -38,11 +62,33 @@ const DEFAULT_FILTER_ENV: &str = "RUST_LOG";
 #[derive(Debug)]
 pub struct Builder {
     filter: FilterBuilder,
+    max_gelf_level: Option<GelfLevel>,
     target: Target,
+    routes: Vec<(String, Target)>,
+    fallback: Option<Target>,
+    newline: bool,
     null_character: bool,
     type_suffix: bool,
     additional_fields: Map<String, Value>,
+    additional_fields_files: Vec<PathBuf>,
     raw_additional_fields: Map<String, Value>,
+    target_raw_additional_fields: Vec<(String, Map<String, Value>)>,
+    record_options: RecordOptions,
+    sequence: bool,
+    drop_flush_timeout: Option<Duration>,
+    drop_report_interval: Option<Duration>,
+    flush_on_drop: bool,
+    strict: bool,
+    sort_fields: bool,
+    keep_last: usize,
+    host_fn: Option<fn() -> String>,
+    dynamic_fields: Vec<(String, fn() -> Value)>,
+    hostname_lookup: bool,
+    dry_run: bool,
+    #[cfg(feature = "http")]
+    compression_level: Option<u32>,
+    /// The first error raised by a setter, returned when building.
+    error: Option<Error>,
 }
 
 impl Builder {
@@ -114,6 +160,14 @@ impl Builder {
         self
     }
 
+    /// Removes every filter directive added so far, e.g. from
+    /// [`Builder::from_default_env`], so that only the ones added afterwards
+    /// apply. Without any directive, only errors are logged.
+    pub fn clear_filters(mut self) -> Self {
+        self.filter = FilterBuilder::default();
+        self
+    }
+
     /// Overwrite the target with the specified one.
     pub fn target(mut self, target: Target) -> Self {
         self.target = target;
@@ -132,6 +186,30 @@ impl Builder {
         self
     }
 
+    /// Overwrite the target to keep the records in memory, and returns the
+    /// handle to read them back. This is meant for tests.
+    pub fn memory(mut self) -> (Self, MemoryHandle) {
+        let memory = MemoryHandle::default();
+        self.target = Target::Memory(memory.clone());
+        (self, memory)
+    }
+
+    /// Send the records to the given target while the background threads of
+    /// the main one can't reach the remote host, instead of spilling or
+    /// dropping them. They are sent to the main target again once reconnected.
+    /// The records queued when the outage starts are handled as usual.
+    pub fn fallback(mut self, target: Target) -> Self {
+        self.fallback = Some(target);
+        self
+    }
+
+    /// Add a target receiving, instead of the main one, the records with a
+    /// `__gelf_target` key set to `name`. The key is not sent as a field.
+    pub fn route(mut self, name: &str, target: Target) -> Self {
+        self.routes.push((name.to_owned(), target));
+        self
+    }
+
     /// Overwrite the target to set it to an TCP target. If `None` is specified
     /// [`TcpTarget::default`] will be used.
     pub fn tcp(mut self, config: Option<TcpTarget>) -> Self {
@@ -139,6 +217,53 @@ impl Builder {
         self
     }
 
+    /// Overwrite the target to set it to an HTTP target. If `None` is specified
+    /// [`HttpTarget::default`] will be used.
+    #[cfg(feature = "http")]
+    pub fn http(mut self, config: Option<HttpTarget>) -> Self {
+        self.target = Target::Http(config.unwrap_or_default());
+        self
+    }
+
+    /// Authenticate the HTTP requests with the given bearer token.
+    ///
+    /// If the target is currently not HTTP, it will first set it.
+    #[cfg(feature = "http")]
+    pub fn http_bearer_token(mut self, token: String) -> Self {
+        self.http_config_or_default()
+            .headers
+            .push(("Authorization".to_owned(), format!("Bearer {token}")));
+        self
+    }
+
+    /// Authenticate the HTTP requests with the given user and password.
+    ///
+    /// If the target is currently not HTTP, it will first set it.
+    #[cfg(feature = "http")]
+    pub fn http_basic_auth(mut self, user: String, password: String) -> Self {
+        let credentials = crate::http::base64(format!("{user}:{password}").as_bytes());
+        self.http_config_or_default()
+            .headers
+            .push(("Authorization".to_owned(), format!("Basic {credentials}")));
+        self
+    }
+
+    /// Set the gzip compression level of the HTTP request bodies, from 0 (no
+    /// compression) to 9 (best compression). Defaults to 6. Building fails if
+    /// the level is out of range.
+    ///
+    /// The level applies to every HTTP target of the logger, whether set up
+    /// before or after this call, and is ignored by the other targets, whose
+    /// records are not compressed.
+    #[cfg(feature = "http")]
+    pub fn compression_level(mut self, level: u32) -> Self {
+        if level > 9 {
+            self.fail(Error::InvalidConfig("compression level above 9"));
+        }
+        self.compression_level = Some(level);
+        self
+    }
+
     /// Set the TCP hostname. This hostname is also used to establish TLS
     /// connexion if the `tls` option is requested.
     ///
@@ -156,7 +281,11 @@ impl Builder {
         self
     }
 
-    /// Enable or disable TLS support.
+    /// Enable or disable TLS support. The TLS backend is selected by the
+    /// `native-tls` (default) or `rustls` features, the former taking
+    /// precedence when both are enabled: using `rustls` requires
+    /// `default-features = false`. The async logger of the `tokio` feature,
+    /// which enables `native-tls`, always uses it.
     ///
     /// If the target is currently not TCP, it will first set it.
     pub fn tls(mut self, tls: bool) -> Self {
@@ -173,6 +302,26 @@ impl Builder {
         self
     }
 
+    /// Set the connection read timeout duration, only used by the TLS
+    /// handshake. If `None` is specified, a stalled handshake can block
+    /// indefinitely.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn read_timeout(mut self, duration: Option<Duration>) -> Self {
+        self.tcp_config_or_default().read_timeout = duration;
+        self
+    }
+
+    /// Connect when the logger is built, retrying up to `retries` times with
+    /// `backoff` in between, so that [`Builder::build`] fails if the target
+    /// can't be reached. By default, the connection is established lazily.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn connect_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.tcp_config_or_default().connect_retries = Some((retries, backoff));
+        self
+    }
+
     /// Set the connection write timeout duration. If `None` is specified, the
     /// socket write calls can block indefinitely.
     ///
@@ -184,8 +333,8 @@ impl Builder {
 
     /// Set the number of messages that can be queued between the caller and
     /// background threads. If too many log calls are made and the background is
-    /// too slow, this buffer will fill up. When full, calls on the current
-    /// thread will start to block.
+    /// too slow, this buffer will fill up. When full, the full buffer policy
+    /// applies, blocking calls on the current thread by default.
     ///
     /// If the target is currently not TCP, it will first set it.
     pub fn buffer_size(mut self, n: usize) -> Self {
@@ -193,6 +342,166 @@ impl Builder {
         self
     }
 
+    /// Bound the queue between the caller and background threads by the total
+    /// size in bytes of the queued records instead of their number. When the
+    /// budget is exceeded, the full buffer policy applies.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn buffer_bytes(mut self, n: usize) -> Self {
+        self.tcp_config_or_default().buffer_bytes = Some(n);
+        self
+    }
+
+    /// Set the behavior of log calls when the buffer between the caller and
+    /// background threads is full: block, drop the record or block for a
+    /// limited duration.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn full_buffer_policy(mut self, policy: FullBufferPolicy) -> Self {
+        self.tcp_config_or_default().full_buffer_policy = policy;
+        self
+    }
+
+    /// Enable or disable writing records directly from the calling thread when
+    /// the connection is established and the background thread is idle. This
+    /// lowers the latency of low-volume loggers, records are never reordered
+    /// relative to the queued ones.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn inline_when_idle(mut self, enabled: bool) -> Self {
+        self.tcp_config_or_default().inline_when_idle = enabled;
+        self
+    }
+
+    /// Store the records that couldn't be queued or sent into a file, up to
+    /// `max_bytes`, and replay them once the connection is established. Records
+    /// left in the file are replayed on the next start.
+    ///
+    /// Records are only spilled when the buffer is full if the full buffer
+    /// policy gives up on waiting, e.g. [`FullBufferPolicy::Discard`].
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn spill_to_disk(mut self, path: PathBuf, max_bytes: u64) -> Self {
+        self.tcp_config_or_default().spill_file = Some(SpillFile { path, max_bytes });
+        self
+    }
+
+    /// Set how each record is delimited on the TCP stream.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.tcp_config_or_default().framing = framing;
+        self
+    }
+
+    /// Set the behavior when writing a record exceeds the write timeout. The
+    /// record is dropped by default, as retrying may duplicate it.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn on_write_timeout(mut self, policy: WriteTimeoutPolicy) -> Self {
+        self.tcp_config_or_default().on_write_timeout = policy;
+        self
+    }
+
+    /// Set the number of background threads sending the records, each with its
+    /// own TCP connection. Records are no longer ordered with more than one.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn worker_count(mut self, n: usize) -> Self {
+        self.tcp_config_or_default().worker_count = n;
+        self
+    }
+
+    /// Drop the records older than `max_age` when the background thread
+    /// dequeues them instead of sending them, e.g. after an outage. They are
+    /// counted in [`GelfLogger::stats`].
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn max_record_age(mut self, max_age: Duration) -> Self {
+        self.tcp_config_or_default().max_record_age = Some(max_age);
+        self
+    }
+
+    /// Stamp the records with `_queued_at` when they are logged and `_sent_at`
+    /// when they are written to the connection, to surface queue delays.
+    /// `_queued_at` is sorted and validated like the other additional fields,
+    /// while `_sent_at` is appended last.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn measure_queue_latency(mut self, enabled: bool) -> Self {
+        self.tcp_config_or_default().measure_queue_latency = enabled;
+        self
+    }
+
+    /// Set the stack size of the background threads sending the records.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn worker_stack_size(mut self, size: usize) -> Self {
+        self.tcp_config_or_default().worker_stack_size = Some(size);
+        self
+    }
+
+    /// Close and reestablish the TCP connection once it has been open for the
+    /// given duration, e.g. to follow certificate rotations or load balancers.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn max_connection_age(mut self, duration: Option<Duration>) -> Self {
+        self.tcp_config_or_default().max_connection_age = duration;
+        self
+    }
+
+    /// Use an unbounded channel between the caller and background threads
+    /// instead of one holding at most [`buffer_size`](Self::buffer_size)
+    /// messages. Log calls will never block, at the expense of an unbounded
+    /// memory usage if the background thread can't keep up, which may lead to
+    /// an out-of-memory condition.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn unbounded(mut self, enabled: bool) -> Self {
+        self.tcp_config_or_default().unbounded = enabled;
+        self
+    }
+
+    /// Establish the connections with the given function instead of connecting
+    /// to the hostname and port, e.g. to go through a tunnel.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn connection_factory(mut self, factory: Option<ConnectionFactory>) -> Self {
+        self.tcp_config_or_default().connection_factory = factory;
+        self
+    }
+
+    /// Buffer the records in the background threads, writing them to the
+    /// connection at once when they would exceed `bytes` or on flush, to
+    /// coalesce small writes while bounding the memory held. Records are held
+    /// until then, so a low traffic service should flush periodically.
+    /// Disabled if `None` is specified, which is the default.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn flush_high_water(mut self, bytes: Option<usize>) -> Self {
+        self.tcp_config_or_default().flush_high_water = bytes;
+        self
+    }
+
+    /// Bind the connections to the given local address, e.g. to leave from a
+    /// specific interface on a multi-homed host. The port can be 0 to let the
+    /// operating system pick one.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn bind_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.tcp_config_or_default().bind_addr = addr;
+        self
+    }
+
+    /// Register a static function that will be called on each socket before it
+    /// connects, to set options like `SO_REUSEADDR`.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn configure_socket(mut self, f: Option<fn(&Socket) -> io::Result<()>>) -> Self {
+        self.tcp_config_or_default().configure_socket = f;
+        self
+    }
+
     /// Register a static function that will be called when errors occur in the
     /// background thread.
     ///
@@ -202,6 +511,30 @@ impl Builder {
         self
     }
 
+    /// Panic in the background thread when an error occurs there, e.g. when
+    /// the remote host is unreachable, instead of ignoring it or calling the
+    /// background error handler. Meant for development, disabled by default.
+    ///
+    /// If the target is currently not TCP, it will first set it.
+    pub fn panic_on_background_error(mut self, enabled: bool) -> Self {
+        self.tcp_config_or_default().panic_on_background_error = enabled;
+        self
+    }
+
+    #[cfg(feature = "http")]
+    fn http_config_or_default(&mut self) -> &mut HttpTarget {
+        match &mut self.target {
+            Target::Http(target) => target,
+            target => {
+                *target = Target::Http(HttpTarget::default());
+                match target {
+                    Target::Http(target) => target,
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
     fn tcp_config_or_default(&mut self) -> &mut TcpTarget {
         match &mut self.target {
             Target::Tcp(target) => target,
@@ -226,6 +559,9 @@ impl Builder {
     ///     .null_character(true)
     ///     .type_suffix(true)
     /// ```
+    ///
+    /// The token is used by every record, unless another one is set for its
+    /// target with [`Builder::ovh_token_for_target`].
     #[cfg(feature = "ovh-ldp")]
     pub fn ovh_ldp(self, hostname: String, token: String) -> Self {
         self.hostname(hostname)
@@ -236,6 +572,14 @@ impl Builder {
             .type_suffix(true)
     }
 
+    /// Enable or disable the newline (`\n`) appended at the end of every
+    /// record, enabled by default. Strictly null-delimited inputs may need it
+    /// disabled along [`Builder::null_character`].
+    pub fn newline(mut self, enabled: bool) -> Self {
+        self.newline = enabled;
+        self
+    }
+
     /// Enable or disable automatic null character (`\0`) appending at the end
     /// of every record. This may be required by some backends.
     pub fn null_character(mut self, enabled: bool) -> Self {
@@ -250,6 +594,326 @@ impl Builder {
         self
     }
 
+    /// Enable or disable walking the [`source`](std::error::Error::source)
+    /// chain of error values captured with `err:err = ...`. Each cause is added
+    /// as an `_error_<n>` field, starting from `_error_0` for the closest one.
+    /// The causes of the following errors of the record are numbered after
+    /// the ones of the previous errors.
+    pub fn capture_error_sources(mut self, enabled: bool) -> Self {
+        self.record_options.capture_error_sources = enabled;
+        self
+    }
+
+    /// Register a static function used to rewrite every `short_message`, e.g.
+    /// to replace embedded `\n` so that the message stays on a single line.
+    pub fn short_message_transform(mut self, f: Option<fn(&str) -> String>) -> Self {
+        self.record_options.short_message_transform = f;
+        self
+    }
+
+    /// Register a static function used to rewrite the value of every record
+    /// field once flattened, given its key (e.g. `_elapsed`). It runs before
+    /// the type suffix is added.
+    pub fn value_transformer(mut self, f: Option<fn(&str, Value) -> Value>) -> Self {
+        self.record_options.value_transformer = f;
+        self
+    }
+
+    /// Set how additional fields set to `null` are represented. They are kept
+    /// as is by default.
+    pub fn null_field_policy(mut self, policy: NullFieldPolicy) -> Self {
+        self.record_options.null_field_policy = policy;
+        self
+    }
+
+    /// Leave out the additional fields set to `None`, including the members and
+    /// elements of the values captured with `:serde`, at any depth, instead of
+    /// sending them as `null`.
+    pub fn omit_none_fields(mut self, enabled: bool) -> Self {
+        self.record_options.omit_none_fields = enabled;
+        self
+    }
+
+    /// Set how additional fields set to `NaN` or an infinite float are
+    /// represented, including the members of the values captured with
+    /// `:serde`. They are serialized as `null` by default.
+    pub fn non_finite_float_policy(mut self, policy: NonFiniteFloatPolicy) -> Self {
+        self.record_options.non_finite_float_policy = policy;
+        self
+    }
+
+    /// Drop the records less severe than the given GELF level, on top of the
+    /// filters. Unlike them, it tells apart the levels set with the `gelf_*`
+    /// macros that share a [`log::Level`], e.g. [`GelfLevel::Emergency`] and
+    /// [`GelfLevel::Error`].
+    pub fn max_gelf_level(mut self, level: Option<GelfLevel>) -> Self {
+        self.max_gelf_level = level;
+        self
+    }
+
+    /// Capture a backtrace into the `full_message` of the records at or above
+    /// the given level, e.g. [`GelfLevel::Error`]. Backtraces are only captured
+    /// when enabled by the `RUST_LIB_BACKTRACE` or `RUST_BACKTRACE` variables.
+    pub fn backtrace_on(mut self, level: Option<GelfLevel>) -> Self {
+        self.record_options.backtrace_level = level;
+        self
+    }
+
+    /// Keep the last `n` records in memory, to be dumped with
+    /// [`GelfLogger::recent`] e.g. when an error occurs, even if they never
+    /// reached the remote host. Disabled when `n` is 0, the default.
+    pub fn keep_last(mut self, n: usize) -> Self {
+        self.keep_last = n;
+        self
+    }
+
+    /// Enable or disable the strict mode, validating every record against the
+    /// GELF requirements (non-empty `short_message`, no `_id` field, valid
+    /// field names...) before sending it. Invalid records are dropped, counted
+    /// in [`GelfLogger::stats`] and reported to the background error handler.
+    ///
+    /// This is meant for development, e.g. `strict(cfg!(debug_assertions))`.
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Enable or disable sorting the additional fields by name, for a stable
+    /// output e.g. in snapshot tests. They keep their insertion order by
+    /// default, which is cheaper.
+    pub fn sort_fields(mut self, enabled: bool) -> Self {
+        self.sort_fields = enabled;
+        self
+    }
+
+    /// Replace the value of the additional fields with the given names by
+    /// `"***"`, e.g. to avoid leaking a password captured with `:serde`. The
+    /// names are matched exactly against the flattened field names before
+    /// their type suffix, the leading `_` being optional.
+    pub fn redact_fields(mut self, names: &[&str]) -> Self {
+        self.record_options
+            .redacted_fields
+            .extend(names.iter().map(|name| match name.starts_with('_') {
+                true => (*name).to_owned(),
+                false => format!("_{name}"),
+            }));
+        self
+    }
+
+    /// Replace empty messages, e.g. from `info!("")`, with the given
+    /// placeholder such as `(empty)`, as Graylog rejects records with an empty
+    /// `short_message`.
+    pub fn empty_message_placeholder(mut self, placeholder: Option<String>) -> Self {
+        self.record_options.empty_message_placeholder = placeholder;
+        self
+    }
+
+    /// Set the casing of the `_levelname` field, e.g. `Informational` by
+    /// default or `info` with [`LevelNameStyle::Short`].
+    pub fn level_name_style(mut self, style: LevelNameStyle) -> Self {
+        self.record_options.level_name_style = style;
+        self
+    }
+
+    /// Set the `host` of every record instead of looking up the hostname of
+    /// the machine.
+    pub fn host(mut self, host: String) -> Self {
+        self.record_options.host = Some(host);
+        self
+    }
+
+    /// Never look up the hostname of the machine, e.g. in sandboxes where the
+    /// lookup is blocked, using `localhost` unless [`Builder::host`] or
+    /// [`Builder::host_fn`] is set.
+    pub fn disable_hostname_lookup(mut self) -> Self {
+        self.hostname_lookup = false;
+        self
+    }
+
+    /// Register a static function computing the `host` of every record, e.g.
+    /// from a mounted file or a metadata endpoint. It is called once when
+    /// building the logger, and takes precedence over the hostname lookup but
+    /// not over [`Builder::host`].
+    pub fn host_fn(mut self, f: Option<fn() -> String>) -> Self {
+        self.host_fn = f;
+        self
+    }
+
+    /// Override the `_facility` of every record, e.g. with the application
+    /// name. The record target, usually the module path, is then recorded in
+    /// the module field instead, see [`Builder::module_field_name`].
+    pub fn facility(mut self, facility: Option<String>) -> Self {
+        self.record_options.facility = facility;
+        self
+    }
+
+    /// Register a static function computing the `_facility` from the record
+    /// target, e.g. to group the modules under `db::` as `database`. Ignored
+    /// if the facility is overridden with [`Builder::facility`].
+    pub fn facility_mapper(mut self, f: Option<fn(&str) -> String>) -> Self {
+        self.record_options.facility_mapper = f;
+        self
+    }
+
+    /// Set the name of the field holding the record target when the facility
+    /// is overridden, `_module` by default. Only used along
+    /// [`Builder::facility`] or [`Builder::emit_module_path`].
+    pub fn module_field_name(mut self, name: &str) -> Self {
+        self.record_options.module_field_name = Some(name.to_owned());
+        self
+    }
+
+    /// Record the module path of the call site in the module field, see
+    /// [`Builder::module_field_name`], while `_facility` keeps the record
+    /// target. They only differ when the target is set explicitly. Disabled by
+    /// default. When the facility is overridden too, the module path replaces
+    /// the target in that field.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gelf_logger::{gelf_info, Builder};
+    ///
+    /// let (builder, memory) = Builder::new()
+    ///     .parse_filters("info")
+    ///     .emit_module_path(true)
+    ///     .memory();
+    /// builder.init();
+    ///
+    /// gelf_info!(target: "svc", "started");
+    ///
+    /// let records = memory.take();
+    /// assert_eq!(records[0].facility.as_deref(), Some("svc"));
+    /// assert_eq!(records[0].get_str("module"), Some(module_path!()));
+    /// ```
+    pub fn emit_module_path(mut self, enabled: bool) -> Self {
+        self.record_options.emit_module_path = enabled;
+        self
+    }
+
+    /// Enable or disable the `timestamp` field, enabled by default. When
+    /// disabled, the server assigns the timestamp on ingest instead of relying
+    /// on the local clock.
+    pub fn timestamp(mut self, enabled: bool) -> Self {
+        self.record_options.omit_timestamp = !enabled;
+        self
+    }
+
+    /// Stamp the records emitted with a trace context, see
+    /// [`set_trace_context`](crate::set_trace_context), with a `_sample_bucket`
+    /// field derived from a hash of the trace id, among `buckets`. Every
+    /// record of a trace lands in the same bucket, so that sampling can happen
+    /// downstream. Disabled by default.
+    pub fn sample_buckets(mut self, buckets: Option<NonZeroU32>) -> Self {
+        self.record_options.sample_buckets = buckets;
+        self
+    }
+
+    /// Convert the [`Duration`] key-values captured with `:serde` to numbers in
+    /// the given unit, suffixing their name with it, e.g. `elapsed_ms`.
+    pub fn duration_unit(mut self, unit: DurationUnit) -> Self {
+        self.record_options.duration_unit = Some(unit);
+        self
+    }
+
+    /// Set what to do when two values are flattened to the same field name,
+    /// e.g. the `a_b` key and the `b` field of an `a` key. The last value
+    /// overwrites the others by default.
+    pub fn field_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.record_options.collision_policy = policy;
+        self
+    }
+
+    /// Truncate the string values of the fields longer than `len` bytes, on a
+    /// character boundary and followed by an ellipsis counted in the `len`
+    /// bytes, to keep the records within the field size limits of the server.
+    /// The `short_message` is left untouched. Disabled if `None` is
+    /// specified, which is the default.
+    pub fn max_field_value_len(mut self, len: Option<usize>) -> Self {
+        self.record_options.max_field_value_len = len;
+        self
+    }
+
+    /// Join the keys of nested values with dots, e.g. `_a.b` instead of `_a_b`
+    /// for `{"a": {"b": 1}}`, as expected by some Graylog extractors. It
+    /// applies to the record key-values and the additional fields. Disabled by
+    /// default.
+    pub fn dotted_keys(mut self, enabled: bool) -> Self {
+        self.record_options.dotted_keys = enabled;
+        self
+    }
+
+    /// Serialize the integer fields beyond 2^53 as strings, since consumers
+    /// parsing numbers as doubles would lose their precision. Disabled by
+    /// default.
+    pub fn large_ints_as_strings(mut self, enabled: bool) -> Self {
+        self.record_options.large_ints_as_strings = enabled;
+        self
+    }
+
+    /// Enable or disable the `_facility` field holding the record target,
+    /// enabled by default. Disabling it reduces the field cardinality when the
+    /// module path isn't useful.
+    pub fn emit_facility(mut self, enabled: bool) -> Self {
+        self.record_options.omit_facility = !enabled;
+        self
+    }
+
+    /// Only send the `version`, `host`, `short_message`, `timestamp` and
+    /// `level` fields besides the additional ones, leaving out `_levelname`,
+    /// `_facility`, `_file` and `_line` to save bandwidth. Disabled by default.
+    ///
+    /// Disabling it doesn't bring back a field left out by another method,
+    /// e.g. [`Builder::emit_facility`].
+    pub fn minimal(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.record_options.omit_level_name = true;
+            self.record_options.omit_facility = true;
+            self.record_options.omit_location = true;
+        }
+        self
+    }
+
+    /// Enable or disable the `_seq` field holding a per-logger counter starting
+    /// at 1. It helps spotting dropped or reordered records downstream.
+    pub fn with_sequence(mut self, enabled: bool) -> Self {
+        self.sequence = enabled;
+        self
+    }
+
+    /// Build and serialize the records as usual, counting the failures in
+    /// [`GelfLogger::stats`], but drop them instead of writing them to the
+    /// targets, which are validated but never connected to. This checks the
+    /// logging setup, e.g. in CI, without a server. Disabled by default, and
+    /// ignored by the async logger.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Set the maximum duration the logger waits, when dropped, for the queued
+    /// records to be written and flushed. If `None` is specified, dropping the
+    /// logger can block indefinitely.
+    pub fn drop_flush_timeout(mut self, duration: Option<Duration>) -> Self {
+        self.drop_flush_timeout = duration;
+        self
+    }
+
+    /// Enable or disable flushing the queued records when the last clone of
+    /// the logger is dropped, which blocks. Enabled by default.
+    pub fn flush_on_drop(mut self, enabled: bool) -> Self {
+        self.flush_on_drop = enabled;
+        self
+    }
+
+    /// Send a warning record summarizing how many records were dropped because
+    /// the background buffer was full, at most once per interval. Disabled if
+    /// `None` is specified, which is the default.
+    pub fn report_dropped_records(mut self, interval: Option<Duration>) -> Self {
+        self.drop_report_interval = interval;
+        self
+    }
+
     /// Add additional fields that will be flatted and added to every GELF
     /// record.
     pub fn extend_additional_fields<T: IntoIterator<Item = (String, Value)>>(
@@ -260,6 +924,54 @@ impl Builder {
         self
     }
 
+    /// Add the fields of a value serializing to a map, e.g. a struct holding
+    /// the service metadata, as additional fields. They are flattened like
+    /// the key-values captured with `:serde`.
+    ///
+    /// Building the logger fails if the value can't be serialized to a map.
+    pub fn additional_fields_from<T: Serialize>(mut self, value: &T) -> Self {
+        match serde_json::to_value(value) {
+            Ok(Value::Object(fields)) => self.additional_fields.extend(fields),
+            Ok(_) => self.fail(Error::InvalidConfig(
+                "additional fields not serializing to a map",
+            )),
+            Err(err) => self.fail(Error::Serialization(err.into())),
+        }
+        self
+    }
+
+    /// Add the fields of the JSON object stored in a file, e.g. the pod
+    /// metadata mounted in a container, as additional fields. Files with a
+    /// `yaml` or `yml` extension are read as a YAML mapping instead, which
+    /// requires the `yaml` feature. The file is read when building the logger,
+    /// which fails with [`Error::Io`] if it can't be read, or with
+    /// [`Error::Serialization`] if it can't be parsed.
+    pub fn additional_fields_from_file(mut self, path: PathBuf) -> Self {
+        self.additional_fields_files.push(path);
+        self
+    }
+
+    /// Add the attributes of an OpenTelemetry
+    /// [`Resource`](opentelemetry_sdk::Resource) as additional fields. Dots in
+    /// attribute keys are replaced, so that `service.name` becomes
+    /// `_service_name`.
+    #[cfg(feature = "otel")]
+    pub fn with_otel_resource(self, resource: opentelemetry_sdk::Resource) -> Self {
+        let fields = resource
+            .iter()
+            .map(|(key, value)| (key.as_str().replace('.', "_"), otel_value(value)))
+            .collect::<Vec<_>>();
+        self.extend_additional_fields(fields)
+    }
+
+    /// Add an additional field whose value is computed by `f` for every
+    /// record, e.g. the current memory usage. It is flattened like the other
+    /// additional fields and should be cheap to compute.
+    pub fn dynamic_field(mut self, key: &str, f: fn() -> Value) -> Self {
+        self.dynamic_fields.push((key.to_owned(), f));
+        self
+    }
+
     /// Add raw additional fields that will be added to every GELF record.
     ///
     /// Certain backend may reject record with unexpect fields.
@@ -280,6 +992,104 @@ impl Builder {
         self
     }
 
+    /// Add raw additional fields that will be added to the GELF records whose
+    /// target is `target` or one of its submodules, e.g. `app::billing`. They
+    /// take precedence over the ones set for every record, and only the most
+    /// specific target applies.
+    pub fn extend_raw_additional_fields_for_target<T: IntoIterator<Item = (String, Value)>>(
+        mut self,
+        target: &str,
+        fields: T,
+    ) -> Self {
+        match self
+            .target_raw_additional_fields
+            .iter_mut()
+            .find(|(prefix, _)| prefix == target)
+        {
+            Some((_, map)) => map.extend(fields),
+            None => self
+                .target_raw_additional_fields
+                .push((target.to_owned(), fields.into_iter().collect())),
+        }
+        self
+    }
+
+    /// Set the `X-OVH-TOKEN` field of the records whose target is `target` or
+    /// one of its submodules, routing them to another LDP stream. Other records
+    /// keep the token set by [`Builder::ovh_token`] or [`Builder::ovh_ldp`].
+    #[cfg(feature = "ovh-ldp")]
+    pub fn ovh_token_for_target(self, target: &str, token: String) -> Self {
+        self.extend_raw_additional_fields_for_target(
+            target,
+            [("X-OVH-TOKEN".to_owned(), Value::String(token))],
+        )
+    }
+
+    /// Returns a human-readable summary of the effective settings, suitable for
+    /// bug reports. Raw additional fields holding secrets, like `X-OVH-TOKEN`,
+    /// and HTTP header values are redacted.
+    pub fn describe(&self) -> String {
+        let mut summary = String::new();
+        let _ = writeln!(summary, "target: {:?}", self.target);
+        for (name, target) in &self.routes {
+            let _ = writeln!(summary, "route[{name}]: {target:?}");
+        }
+        if let Some(target) = &self.fallback {
+            let _ = writeln!(summary, "fallback: {target:?}");
+        }
+        let _ = writeln!(summary, "filter: {:?}", self.filter);
+        let _ = writeln!(summary, "max_gelf_level: {:?}", self.max_gelf_level);
+        let _ = writeln!(summary, "newline: {}", self.newline);
+        let _ = writeln!(summary, "null_character: {}", self.null_character);
+        let _ = writeln!(summary, "type_suffix: {}", self.type_suffix);
+        let _ = writeln!(
+            summary,
+            "additional_fields: {}",
+            Value::Object(self.additional_fields.clone())
+        );
+        let _ = writeln!(
+            summary,
+            "additional_fields_files: {:?}",
+            self.additional_fields_files
+        );
+        let _ = writeln!(
+            summary,
+            "dynamic_fields: {:?}",
+            self.dynamic_fields
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        );
+        let _ = writeln!(
+            summary,
+            "raw_additional_fields: {}",
+            redact(&self.raw_additional_fields)
+        );
+        for (target, fields) in &self.target_raw_additional_fields {
+            let _ = writeln!(
+                summary,
+                "raw_additional_fields[{target}]: {}",
+                redact(fields)
+            );
+        }
+        let _ = writeln!(summary, "record_options: {:?}", self.record_options);
+        let _ = writeln!(summary, "sequence: {}", self.sequence);
+        let _ = writeln!(summary, "strict: {}", self.strict);
+        let _ = writeln!(summary, "sort_fields: {}", self.sort_fields);
+        let _ = writeln!(summary, "keep_last: {}", self.keep_last);
+        let _ = writeln!(summary, "dry_run: {}", self.dry_run);
+        #[cfg(feature = "http")]
+        let _ = writeln!(summary, "compression_level: {:?}", self.compression_level);
+        let _ = writeln!(summary, "flush_on_drop: {}", self.flush_on_drop);
+        let _ = writeln!(summary, "drop_flush_timeout: {:?}", self.drop_flush_timeout);
+        let _ = writeln!(
+            summary,
+            "drop_report_interval: {:?}",
+            self.drop_report_interval
+        );
+        summary
+    }
+
     /// Initializes the global logger with the built env logger.
     ///
     /// This should be called early in the execution of a Rust program. Any log
@@ -312,18 +1122,142 @@ impl Builder {
         self.try_init().expect("logger initialization failure");
     }
 
+    /// Build the final `GelfLogger` along with a handle to flush it and read
+    /// its stats, without registering it as the global logger. This suits
+    /// tests and libraries embedding their own logger, which call
+    /// [`Log::log`](log::Log::log) on it directly.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gelf_logger::Builder;
+    /// use log::{Level, Log, Record};
+    ///
+    /// let (logger, handle) = Builder::new().stdout().build_handle().unwrap();
+    /// logger.log(&Record::builder().args(format_args!("hello")).level(Level::Error).build());
+    /// handle.flush();
+    /// ```
+    pub fn build_handle(self) -> Result<(GelfLogger, GelfHandle), Error> {
+        let logger = self.build()?;
+        let handle = logger.handle();
+        Ok((logger, handle))
+    }
+
     /// Build the final `GelfLogger`.
     pub fn build(mut self) -> Result<GelfLogger, Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        for path in &self.additional_fields_files {
+            self.additional_fields.extend(read_fields(path)?);
+        }
+        #[cfg(feature = "http")]
+        if let Some(level) = self.compression_level {
+            for target in iter::once(&mut self.target)
+                .chain(self.routes.iter_mut().map(|(_, target)| target))
+                .chain(&mut self.fallback)
+            {
+                if let Target::Http(target) = target {
+                    target.compression_level = level;
+                }
+            }
+        }
+        validate(&self.target)?;
+        for target in self
+            .routes
+            .iter()
+            .map(|(_, target)| target)
+            .chain(&self.fallback)
+        {
+            validate(target)?;
+        }
+        if self.record_options.host.is_none() {
+            self.record_options.host = match self.host_fn {
+                Some(f) => Some(f()),
+                None => (!self.hostname_lookup).then(|| "localhost".to_owned()),
+            };
+        }
+        let counters = Arc::default();
+        let new_writer = |target| match self.dry_run {
+            true => Ok(Writer::Discard),
+            false => Writer::new(target, &counters),
+        };
+        let writer = new_writer(self.target)?;
+        let routes: Vec<_> = self
+            .routes
+            .into_iter()
+            .map(|(name, target)| Ok((name, new_writer(target)?)))
+            .collect::<Result<_, Error>>()?;
+        let fallback = match self.fallback {
+            Some(target) => Some(Arc::new(new_writer(target)?)),
+            None => None,
+        };
+        if fallback.is_some() {
+            for writer in iter::once(&writer).chain(routes.iter().map(|(_, writer)| writer)) {
+                writer.probe_when_down();
+            }
+        }
         Ok(GelfLogger {
             filter: self.filter.build(),
-            writer: Writer::new(self.target)?,
-            null_character: self.null_character,
-            additional_fields: flatten(self.additional_fields, Some("_"), "_", self.type_suffix)
+            max_gelf_level: self.max_gelf_level,
+            writer: Arc::new(writer),
+            routes: routes
                 .into_iter()
-                .chain(self.raw_additional_fields)
+                .map(|(name, writer)| (name, Arc::new(writer)))
                 .collect(),
+            fallback,
+            newline: self.newline,
+            null_character: self.null_character,
+            additional_fields: flatten_with(
+                self.additional_fields,
+                Some("_"),
+                self.record_options.separator(),
+                self.type_suffix,
+                LeafOptions {
+                    transform: None,
+                    ..self.record_options.leaf_options()
+                },
+            )
+            .into_iter()
+            .chain(self.raw_additional_fields)
+            .collect(),
+            target_raw_additional_fields: self.target_raw_additional_fields,
+            dynamic_fields: self.dynamic_fields,
+            type_suffix: self.type_suffix,
+            record_options: self.record_options,
+            sequence: self.sequence.then(|| Arc::new(AtomicU64::new(1))),
+            drop_flush_timeout: self.drop_flush_timeout,
+            drop_report_interval: self.drop_report_interval,
+            flush_on_drop: self.flush_on_drop,
+            strict: self.strict,
+            sort_fields: self.sort_fields,
+            counters,
+            recent: (self.keep_last > 0).then(|| Arc::new(Recent::new(self.keep_last))),
         })
     }
+
+    /// Keep the error to return it when building, unless one was already
+    /// raised.
+    fn fail(&mut self, err: Error) {
+        self.error.get_or_insert(err);
+    }
+}
+
+#[cfg(feature = "otel")]
+fn otel_value(value: &opentelemetry::Value) -> Value {
+    use opentelemetry::{Array, Value as OtelValue};
+
+    match value {
+        OtelValue::Bool(v) => Value::from(*v),
+        OtelValue::I64(v) => Value::from(*v),
+        OtelValue::F64(v) => Value::from(*v),
+        OtelValue::String(v) => Value::from(v.as_str()),
+        OtelValue::Array(Array::Bool(v)) => Value::from(v.clone()),
+        OtelValue::Array(Array::I64(v)) => Value::from(v.clone()),
+        OtelValue::Array(Array::F64(v)) => Value::from(v.clone()),
+        OtelValue::Array(Array::String(v)) => v.iter().map(|v| Value::from(v.as_str())).collect(),
+        value => Value::String(value.to_string()),
+    }
 }
 
 impl Default for Builder {
@@ -332,11 +1266,413 @@ impl Default for Builder {
     fn default() -> Self {
         Self {
             filter: FilterBuilder::default(),
+            max_gelf_level: None,
             target: Target::Stderr,
+            routes: Vec::new(),
+            fallback: None,
+            newline: true,
             null_character: false,
             type_suffix: false,
             additional_fields: Map::new(),
+            additional_fields_files: Vec::new(),
             raw_additional_fields: Map::new(),
+            target_raw_additional_fields: Vec::new(),
+            record_options: RecordOptions::default(),
+            sequence: false,
+            drop_flush_timeout: None,
+            drop_report_interval: None,
+            flush_on_drop: true,
+            strict: false,
+            sort_fields: false,
+            keep_last: 0,
+            host_fn: None,
+            dynamic_fields: Vec::new(),
+            hostname_lookup: true,
+            dry_run: false,
+            #[cfg(feature = "http")]
+            compression_level: None,
+            error: None,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Builder {
+    /// Build an [`AsyncGelfLogger`] writing the records to the TCP target from
+    /// the calling task instead of a background thread. The buffering options
+    /// and the connection factory of the target are ignored. TLS connections use
+    /// `native-tls`, which the `tokio` feature enables, so `rustls` is never
+    /// used along with it.
+    ///
+    /// Fails if the target is not TCP.
+    pub fn build_async(mut self) -> Result<AsyncGelfLogger, Error> {
+        validate(&self.target)?;
+        let Target::Tcp(target) = std::mem::replace(&mut self.target, Target::Stderr) else {
+            return Err(Error::InvalidConfig("async logger requires a TCP target"));
+        };
+        Ok(AsyncGelfLogger::new(self.build()?, target))
+    }
+}
+
+/// Check the target settings that would otherwise only fail once connecting.
+fn validate(target: &Target) -> Result<(), Error> {
+    match target {
+        Target::Tcp(target) if target.hostname.is_empty() => {
+            Err(Error::InvalidConfig("empty TCP hostname"))
+        }
+        Target::Tcp(target) if target.port == 0 => Err(Error::InvalidConfig("TCP port 0")),
+        #[cfg(feature = "http")]
+        Target::Http(target) if target.url.is_empty() => {
+            Err(Error::InvalidConfig("empty HTTP url"))
+        }
+        #[cfg(feature = "http")]
+        Target::Http(target) if target.compression_level > 9 => {
+            Err(Error::InvalidConfig("compression level above 9"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Read a JSON object, or a YAML mapping given its extension, from the file.
+fn read_fields(path: &Path) -> Result<Map<String, Value>, Error> {
+    let data = fs::read(path)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        #[cfg(feature = "yaml")]
+        Some("yaml" | "yml") => serde_yaml::from_slice(&data)
+            .map_err(|err| Error::Serialization(io::Error::new(io::ErrorKind::InvalidData, err))),
+        #[cfg(not(feature = "yaml"))]
+        Some("yaml" | "yml") => Err(Error::InvalidConfig(
+            "reading YAML files requires the `yaml` feature",
+        )),
+        _ => serde_json::from_slice(&data).map_err(|err| Error::Serialization(err.into())),
+    }
+}
+
+/// Replace the values of the raw additional fields likely to hold a secret.
+fn redact(fields: &Map<String, Value>) -> Value {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let lowercase = key.to_ascii_lowercase();
+            let secret = ["token", "secret", "password", "authorization"]
+                .iter()
+                .any(|word| lowercase.contains(word));
+            let value = if secret {
+                Value::from("<redacted>")
+            } else {
+                value.clone()
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io,
+        net::TcpListener,
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    };
+
+    use log::{Level, Log, Record};
+
+    use super::Builder;
+    use crate::{Error, GelfLevel, Value, INTERNAL_LEVEL_FIELD_NAME};
+
+    #[test]
+    fn validate() {
+        let result = Builder::new().hostname(String::new()).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        let result = Builder::new().port(0).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn clear_filters() {
+        let logger = Builder::new()
+            .parse_filters("app=debug,info")
+            .clear_filters()
+            .filter_module("db", log::LevelFilter::Trace)
+            .build()
+            .unwrap();
+        let enabled = |target, level| {
+            logger.enabled(&log::Metadata::builder().target(target).level(level).build())
+        };
+        assert!(enabled("db", Level::Trace));
+        assert!(!enabled("app", Level::Debug));
+        assert!(!enabled("other", Level::Error));
+    }
+
+    #[test]
+    fn build_handle() {
+        let (builder, memory) = Builder::new().strict(true).memory();
+        let (logger, handle) = builder.build_handle().unwrap();
+        logger.log(&Record::builder().level(Level::Error).build());
+        logger.log(
+            &Record::builder()
+                .args(format_args!("valid"))
+                .level(Level::Error)
+                .build(),
+        );
+        assert!(handle.flush_timeout(std::time::Duration::from_secs(1)));
+
+        assert_eq!(memory.take().len(), 1);
+        assert_eq!(handle.stats().invalid, 1);
+        assert!(handle.shutdown(std::time::Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn host_fn() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        fn pod_name() -> String {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            "pod-1".to_owned()
+        }
+
+        let record = Record::builder().level(Level::Error).build();
+        let (builder, memory) = Builder::new().host_fn(Some(pod_name)).memory();
+        let logger = builder.build().unwrap();
+        logger.log(&record);
+        logger.log(&record);
+        assert!(memory.take().iter().all(|record| record.host == "pod-1"));
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        // An explicit host skips the lookup altogether.
+        let (builder, memory) = Builder::new()
+            .host_fn(Some(pod_name))
+            .host("api-1".to_owned())
+            .memory();
+        builder.build().unwrap().log(&record);
+        assert_eq!(memory.take()[0].host, "api-1");
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn additional_fields_from() {
+        #[derive(serde::Serialize)]
+        struct Build {
+            version: &'static str,
+            commit: Commit,
+        }
+
+        #[derive(serde::Serialize)]
+        struct Commit {
+            sha: &'static str,
+            dirty: bool,
         }
+
+        let logger = Builder::new()
+            .additional_fields_from(&Build {
+                version: "1.2.3",
+                commit: Commit {
+                    sha: "abc123",
+                    dirty: false,
+                },
+            })
+            .build()
+            .unwrap();
+        assert_eq!(logger.additional_fields["_version"], "1.2.3");
+        assert_eq!(logger.additional_fields["_commit_sha"], "abc123");
+        assert_eq!(logger.additional_fields["_commit_dirty"], false);
+
+        let result = Builder::new().additional_fields_from(&"scalar").build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn additional_fields_from_file() {
+        let path =
+            std::env::temp_dir().join(format!("gelf_logger_fields_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"pod": "api-7f9c", "node": {"zone": "eu-west-1a"}}"#,
+        )
+        .unwrap();
+        let (builder, memory) = Builder::new()
+            .additional_fields_from_file(path.clone())
+            .memory();
+        builder
+            .build()
+            .unwrap()
+            .log(&Record::builder().level(Level::Error).build());
+        let records = memory.take();
+        assert_eq!(records[0].get_str("pod"), Some("api-7f9c"));
+        assert_eq!(records[0].get_str("node_zone"), Some("eu-west-1a"));
+
+        // A malformed file is told apart from a missing one.
+        std::fs::write(&path, "pod: api-7f9c").unwrap();
+        let result = Builder::new()
+            .additional_fields_from_file(path.clone())
+            .build();
+        assert!(matches!(result, Err(Error::Serialization(_))));
+        std::fs::remove_file(&path).unwrap();
+
+        let result = Builder::new().additional_fields_from_file(path).build();
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn additional_fields_from_yaml_file() {
+        let path =
+            std::env::temp_dir().join(format!("gelf_logger_fields_{}.yaml", std::process::id()));
+        std::fs::write(&path, "pod: api-7f9c\nnode:\n  zone: eu-west-1a\n").unwrap();
+        let (builder, memory) = Builder::new()
+            .additional_fields_from_file(path.clone())
+            .memory();
+        builder
+            .build()
+            .unwrap()
+            .log(&Record::builder().level(Level::Error).build());
+        let records = memory.take();
+        assert_eq!(records[0].get_str("pod"), Some("api-7f9c"));
+        assert_eq!(records[0].get_str("node_zone"), Some("eu-west-1a"));
+
+        std::fs::write(&path, "- api-7f9c").unwrap();
+        let result = Builder::new()
+            .additional_fields_from_file(path.clone())
+            .build();
+        assert!(matches!(result, Err(Error::Serialization(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dry_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let logger = Builder::new()
+            .port(listener.local_addr().unwrap().port())
+            .dry_run(true)
+            .build()
+            .unwrap();
+        // JSON objects can't have tuple keys.
+        let tuple_keys = BTreeMap::from([((1, 2), "value")]);
+        let kvs = [("broken", log::kv::Value::from_serde(&tuple_keys))];
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+        logger.flush();
+
+        assert_eq!(logger.stats().serialization_errors, 1);
+        assert_eq!(logger.stats().errors, 0);
+        let err = listener.accept().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn minimal() {
+        let (builder, memory) = Builder::new().minimal(true).memory();
+        builder.build().unwrap().log(
+            &Record::builder()
+                .args(format_args!("compact"))
+                .level(Level::Error)
+                .file(Some(file!()))
+                .line(Some(line!()))
+                .build(),
+        );
+
+        let record = serde_json::to_value(&memory.take()[0]).unwrap();
+        let keys: Vec<_> = record.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            ["version", "host", "short_message", "timestamp", "level"]
+        );
+
+        // The order of the calls doesn't matter.
+        let (builder, memory) = Builder::new().emit_facility(false).minimal(false).memory();
+        builder
+            .build()
+            .unwrap()
+            .log(&Record::builder().level(Level::Error).build());
+        let record = serde_json::to_value(&memory.take()[0]).unwrap();
+        assert!(record.get("_facility").is_none());
+        assert!(record.get("_levelname").is_some());
+    }
+
+    #[test]
+    fn max_gelf_level() {
+        let (builder, memory) = Builder::new()
+            .max_gelf_level(Some(GelfLevel::Critical))
+            .memory();
+        let logger = builder.build().unwrap();
+        for level in [GelfLevel::Error, GelfLevel::Emergency] {
+            let kvs = [(INTERNAL_LEVEL_FIELD_NAME, level as u32)];
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+
+        let records = memory.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, Some(GelfLevel::Emergency as u32));
+    }
+
+    #[test]
+    fn dynamic_field() {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let (builder, memory) = Builder::new()
+            .dynamic_field("counter", || {
+                Value::from(COUNTER.fetch_add(1, Ordering::Relaxed))
+            })
+            .dynamic_field("version", || Value::from("1.2.3"))
+            .memory();
+        let logger = builder.build().unwrap();
+        for _ in 0..2 {
+            logger.log(&Record::builder().level(Level::Error).build());
+        }
+
+        let records = memory.take();
+        assert_eq!(records[0].get_i64("_counter"), Some(0));
+        assert_eq!(records[1].get_i64("_counter"), Some(1));
+        assert_eq!(records[1].get_str("_version"), Some("1.2.3"));
+    }
+
+    #[test]
+    fn describe() {
+        let summary = Builder::new()
+            .hostname("graylog.example.com".to_owned())
+            .port(12202)
+            .extend_raw_additional_fields([
+                ("X-OVH-TOKEN".to_owned(), Value::from("secret-token")),
+                ("_app".to_owned(), Value::from("api")),
+            ])
+            .describe();
+        assert!(summary.contains("graylog.example.com"));
+        assert!(summary.contains("12202"));
+        assert!(summary.contains("\"X-OVH-TOKEN\":\"<redacted>\""));
+        assert!(summary.contains("\"_app\":\"api\""));
+        assert!(!summary.contains("secret-token"));
+    }
+
+    #[test]
+    #[cfg(feature = "otel")]
+    fn otel_resource() {
+        use opentelemetry::KeyValue;
+        use opentelemetry_sdk::Resource;
+
+        let resource = Resource::builder_empty()
+            .with_attributes([
+                KeyValue::new("service.name", "api"),
+                KeyValue::new("service.version", "1.2.3"),
+                KeyValue::new("deployment.environment", "production"),
+            ])
+            .build();
+        let logger = Builder::new().with_otel_resource(resource).build().unwrap();
+        assert_eq!(logger.additional_fields["_service_name"], "api");
+        assert_eq!(logger.additional_fields["_service_version"], "1.2.3");
+        assert_eq!(
+            logger.additional_fields["_deployment_environment"],
+            "production"
+        );
     }
 }
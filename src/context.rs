@@ -0,0 +1,75 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{cell::RefCell, marker::PhantomData, num::NonZeroU32};
+
+thread_local! {
+    static TRACE_CONTEXT: RefCell<Option<TraceContext>> = const { RefCell::new(None) };
+}
+
+/// The trace context attached to records emitted by the current thread.
+#[derive(Clone, Debug)]
+pub(crate) struct TraceContext {
+    pub(crate) trace_id: String,
+    pub(crate) span_id: Option<String>,
+}
+
+/// Set the trace context of the current thread. Every record emitted by this
+/// thread will carry the `_trace_id` and `_span_id` fields until the returned
+/// guard is dropped, restoring the previous context.
+///
+/// # Examples
+///
+/// ```rust
+/// use gelf_logger::set_trace_context;
+/// use log::info;
+///
+/// {
+///     let _guard = set_trace_context(
+///         "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+///         Some("00f067aa0ba902b7".to_owned()),
+///     );
+///     info!("correlated with the trace");
+/// }
+/// info!("not correlated");
+/// ```
+pub fn set_trace_context(trace_id: String, span_id: Option<String>) -> TraceContextGuard {
+    let context = TraceContext { trace_id, span_id };
+    TraceContextGuard {
+        previous: TRACE_CONTEXT.with(|cell| cell.replace(Some(context))),
+        _not_send: PhantomData,
+    }
+}
+
+/// Run the function with the trace context of the current thread, if any.
+pub(crate) fn with_trace_context<T>(f: impl FnOnce(Option<&TraceContext>) -> T) -> T {
+    TRACE_CONTEXT.with(|cell| f(cell.borrow().as_ref()))
+}
+
+/// The bucket of the trace among `buckets`, from a FNV-1a hash of its id so
+/// that every service assigns the same one.
+pub(crate) fn sample_bucket(trace_id: &str, buckets: NonZeroU32) -> u32 {
+    let hash = trace_id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    (hash % u64::from(buckets.get())) as u32
+}
+
+/// Restore the previous trace context of the thread when dropped.
+#[must_use = "the trace context is cleared when the guard is dropped"]
+#[derive(Debug)]
+pub struct TraceContextGuard {
+    previous: Option<TraceContext>,
+    // The guard must be dropped by the thread that created it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for TraceContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        TRACE_CONTEXT.with(|cell| *cell.borrow_mut() = previous);
+    }
+}
@@ -2,7 +2,9 @@
 // license that can be found in the LICENSE file.
 // Copyright 2024 The gelf_logger Authors. All rights reserved.
 
-use std::{io, net::TcpStream};
+use std::io;
+#[cfg(feature = "native-tls")]
+use std::net::TcpStream;
 
 use thiserror::Error as ThisError;
 
@@ -17,9 +19,64 @@ pub enum Error {
     #[error("io failure")]
     Io(#[from] io::Error),
     /// Occurs when the TLS handshake fails.
+    #[cfg(feature = "native-tls")]
     #[error("tls handshake failure")]
     TlsHandshake(#[from] native_tls::HandshakeError<TcpStream>),
     /// Occurs when any TLS error happen.
+    #[cfg(feature = "native-tls")]
     #[error("tls connection failure")]
     Tls(#[from] native_tls::Error),
+    /// Occurs when any TLS error happen with the `rustls` backend.
+    #[cfg(feature = "rustls")]
+    #[error("tls connection failure")]
+    Rustls(#[from] rustls::Error),
+    /// Occurs when the hostname can't be used as a TLS server name.
+    #[cfg(feature = "rustls")]
+    #[error("invalid tls server name")]
+    InvalidServerName(#[from] rustls::pki_types::InvalidDnsNameError),
+    /// Occurs when an HTTP request fails or is rejected.
+    #[cfg(feature = "http")]
+    #[error("http request failure")]
+    Http(#[from] Box<ureq::Error>),
+    /// Occurs when the builder configuration is invalid.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(&'static str),
+    /// Occurs when a record doesn't meet the GELF requirements in strict mode.
+    #[error("invalid record: {0}")]
+    InvalidRecord(&'static str),
+    /// Occurs when converting an integer outside of the 0-7 syslog range to a
+    /// [`GelfLevel`](crate::GelfLevel).
+    #[error("invalid level: {0}")]
+    InvalidLevel(u32),
+    /// Occurs when a field or a record can't be serialized to JSON.
+    #[error("serialization failure")]
+    Serialization(#[source] io::Error),
+    /// Occurs when TLS is requested but no TLS backend feature is enabled.
+    #[error("no tls backend enabled")]
+    TlsUnavailable,
+}
+
+impl Error {
+    /// Returns the kind of the underlying I/O error, if any, e.g. to tell
+    /// `ConnectionRefused` from `TimedOut` in a background error handler.
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            Error::Io(err) => Some(err.kind()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Error;
+
+    #[test]
+    fn io_kind() {
+        let err = Error::from(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(err.io_kind(), Some(io::ErrorKind::TimedOut));
+        assert_eq!(Error::TlsUnavailable.io_kind(), None);
+    }
 }
@@ -0,0 +1,591 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant},
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{
+    logger::{handle_background_error, trim_delimiters, Op, Receiver},
+    Error,
+};
+
+/// An HTTP target used to POST the GELF records to a Graylog HTTP input.
+#[derive(Clone)]
+pub struct HttpTarget {
+    /// The URL of the HTTP input, e.g. `https://graylog.example.com:12201/gelf`.
+    pub url: String,
+    /// Additional headers attached to every request, e.g. for authentication.
+    pub headers: Vec<(String, String)>,
+    /// Compress the request bodies and set `Content-Encoding: gzip`.
+    pub gzip: bool,
+    /// Set the size in bytes under which request bodies are sent uncompressed
+    /// even if `gzip` is enabled, as compressing them is not worth it.
+    pub min_compress_size: usize,
+    /// Set the gzip compression level, from 0 (no compression) to 9 (best
+    /// compression).
+    pub compression_level: u32,
+    /// Set the maximum number of records sent in a single request. Records are
+    /// sent one by one by default.
+    pub batch_size: usize,
+    /// Set the maximum duration a record can wait for its batch to be full
+    /// before being sent. If `None` is specified, partial batches are only sent
+    /// on flush.
+    pub flush_interval: Option<Duration>,
+    /// Set the body format of the requests, depending on what the HTTP input
+    /// accepts.
+    pub batch_format: BatchFormat,
+    /// Set the overall timeout of each request. If `None` is specified, the
+    /// requests can block indefinitely.
+    pub timeout: Option<Duration>,
+    /// Set the number of messages that can be queued between the caller and
+    /// background threads. When full, log calls block on the current thread.
+    pub buffer_size: usize,
+    /// Register a static function that will be called when errors occur in the
+    /// background thread.
+    pub background_error_handler: Option<fn(Error)>,
+}
+
+impl Default for HttpTarget {
+    /// Crate HTTP target with the following placeholders:
+    /// ```rust,ignore
+    /// HttpTarget {
+    ///     url: "http://127.0.0.1:12201/gelf".to_owned(),
+    ///     headers: Vec::new(),
+    ///     gzip: false,
+    ///     min_compress_size: 256,
+    ///     compression_level: 6,
+    ///     batch_size: 1,
+    ///     flush_interval: Some(Duration::from_secs(1)),
+    ///     batch_format: BatchFormat::NewlineDelimited,
+    ///     timeout: None,
+    ///     buffer_size: 1_000,
+    ///     background_error_handler: None,
+    /// }
+    /// ```
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:12201/gelf".to_owned(),
+            headers: Vec::new(),
+            gzip: false,
+            min_compress_size: 256,
+            compression_level: 6,
+            batch_size: 1,
+            flush_interval: Some(Duration::from_secs(1)),
+            batch_format: BatchFormat::NewlineDelimited,
+            timeout: None,
+            buffer_size: 1_000,
+            background_error_handler: None,
+        }
+    }
+}
+
+impl fmt::Debug for HttpTarget {
+    /// Header values are redacted, as they usually hold credentials.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: Vec<_> = self
+            .headers
+            .iter()
+            .map(|(name, _)| (name, "<redacted>"))
+            .collect();
+        f.debug_struct("HttpTarget")
+            .field("url", &self.url)
+            .field("headers", &headers)
+            .field("gzip", &self.gzip)
+            .field("min_compress_size", &self.min_compress_size)
+            .field("compression_level", &self.compression_level)
+            .field("batch_size", &self.batch_size)
+            .field("flush_interval", &self.flush_interval)
+            .field("batch_format", &self.batch_format)
+            .field("timeout", &self.timeout)
+            .field("buffer_size", &self.buffer_size)
+            .field("background_error_handler", &self.background_error_handler)
+            .finish()
+    }
+}
+
+/// The body format of requests holding several records.
+///
+/// Batching is only available on HTTP targets: stream transports like TCP
+/// expect one record per frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BatchFormat {
+    /// One record per line.
+    #[default]
+    NewlineDelimited,
+    /// A JSON array of records.
+    JsonArray,
+}
+
+/// The background thread of an HTTP target.
+pub(crate) struct Worker {
+    target: HttpTarget,
+    agent: ureq::Agent,
+}
+
+impl Worker {
+    pub(crate) fn new(target: HttpTarget) -> Result<Self, Error> {
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(timeout) = target.timeout {
+            agent = agent.timeout(timeout);
+        }
+        #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+        {
+            agent = agent.tls_config(crate::tls::rustls_config());
+        }
+        #[cfg(feature = "native-tls")]
+        {
+            agent = agent.tls_connector(std::sync::Arc::new(native_tls::TlsConnector::new()?));
+        }
+        Ok(Self {
+            target,
+            agent: agent.build(),
+        })
+    }
+
+    pub(crate) fn run(self, rx: Receiver) {
+        let mut batch = Vec::new();
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let op = match deadline {
+                Some(deadline) => {
+                    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(op) => Some(op),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match rx.recv() {
+                    Ok(op) => Some(op),
+                    Err(_) => break,
+                },
+            };
+            match op {
+                Some(Op::Data(data)) => self.push(&mut batch, &mut deadline, [data]),
+                Some(Op::Batch(records)) => self.push(&mut batch, &mut deadline, records),
+                Some(Op::Flush(tx)) => {
+                    self.send(&mut batch);
+                    deadline = None;
+                    let _ = tx.send(());
+                }
+                // Every request opens its own connection.
+                Some(Op::Connect(tx)) => {
+                    let _ = tx.send(Ok(()));
+                }
+                None => {
+                    self.send(&mut batch);
+                    deadline = None;
+                }
+            }
+        }
+        self.send(&mut batch);
+    }
+
+    /// Add the records to the pending ones, sending them once there are enough.
+    fn push(
+        &self,
+        batch: &mut Vec<Vec<u8>>,
+        deadline: &mut Option<Instant>,
+        records: impl IntoIterator<Item = Vec<u8>>,
+    ) {
+        if batch.is_empty() {
+            *deadline = self
+                .target
+                .flush_interval
+                .map(|interval| Instant::now() + interval);
+        }
+        batch.extend(records);
+        if batch.len() >= self.target.batch_size {
+            self.send(batch);
+            *deadline = None;
+        }
+    }
+
+    /// Send the pending records in a single request, if any.
+    fn send(&self, batch: &mut Vec<Vec<u8>>) {
+        if batch.is_empty() {
+            return;
+        }
+        let body = join(batch, self.target.batch_format);
+        batch.clear();
+        handle_background_error(self.target.background_error_handler, self.post(&body));
+    }
+
+    fn post(&self, body: &[u8]) -> Result<(), Error> {
+        let mut request = self
+            .agent
+            .post(&self.target.url)
+            .set("Content-Type", "application/json");
+        for (name, value) in &self.target.headers {
+            request = request.set(name, value);
+        }
+        let response = if self.target.gzip && body.len() >= self.target.min_compress_size {
+            request
+                .set("Content-Encoding", "gzip")
+                .send_bytes(&compress(body, self.target.compression_level)?)
+        } else {
+            request.send_bytes(body)
+        };
+        response.map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+/// Compress the request body with the given level.
+fn compress(body: &[u8], level: u32) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Join the records in a request body, without their trailing delimiters.
+fn join(records: &[Vec<u8>], format: BatchFormat) -> Vec<u8> {
+    let (start, separator, end) = match format {
+        BatchFormat::NewlineDelimited => ("", "\n", ""),
+        BatchFormat::JsonArray => ("[", ",", "]"),
+    };
+    let mut body = start.as_bytes().to_vec();
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            body.extend_from_slice(separator.as_bytes());
+        }
+        body.extend_from_slice(trim_delimiters(record));
+    }
+    body.extend_from_slice(end.as_bytes());
+    body
+}
+
+/// Encode the input in standard base64 with padding, as used by the basic
+/// authentication scheme.
+pub(crate) fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use flate2::read::GzDecoder;
+    use log::{Level, Log, Record};
+    use serde_json::Value;
+
+    use super::{base64, compress, join, BatchFormat};
+    use crate::{Builder, HttpTarget, Target};
+
+    struct Request {
+        headers: Vec<String>,
+        body: Vec<u8>,
+    }
+
+    /// Serve `count` requests on a local port, answering each with a `200 OK`.
+    fn serve(count: usize) -> (u16, thread::JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            while requests.len() < count {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while requests.len() < count {
+                    let mut headers = Vec::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end().to_owned();
+                        if line.is_empty() {
+                            break;
+                        }
+                        headers.push(line);
+                    }
+                    if headers.is_empty() {
+                        break;
+                    }
+                    let len = headers
+                        .iter()
+                        .find_map(|h| {
+                            h.to_lowercase()
+                                .strip_prefix("content-length: ")?
+                                .parse()
+                                .ok()
+                        })
+                        .unwrap_or(0);
+                    let mut body = vec![0; len];
+                    reader.read_exact(&mut body).unwrap();
+                    (&stream)
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .unwrap();
+                    requests.push(Request { headers, body });
+                }
+            }
+            requests
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn post() {
+        let (port, server) = serve(2);
+        let logger = Builder::new()
+            .target(Target::Http(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                headers: vec![("X-Token".to_owned(), "secret".to_owned())],
+                ..HttpTarget::default()
+            }))
+            .null_character(true)
+            .build()
+            .unwrap();
+        for message in ["first", "second"] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].headers[0].starts_with("POST /gelf "));
+        assert!(requests[0]
+            .headers
+            .contains(&"Content-Type: application/json".to_owned()));
+        assert!(requests[0].headers.contains(&"X-Token: secret".to_owned()));
+        let record: Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(record["version"], "1.1");
+        assert_eq!(record["short_message"], "second");
+    }
+
+    #[test]
+    fn gzip() {
+        let (port, server) = serve(2);
+        let logger = Builder::new()
+            .target(Target::Http(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                gzip: true,
+                min_compress_size: 1024,
+                ..HttpTarget::default()
+            }))
+            .build()
+            .unwrap();
+        let large = "compressed ".repeat(100);
+        for message in ["raw", &large] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let requests = server.join().unwrap();
+        let gzip = "Content-Encoding: gzip".to_owned();
+        assert!(!requests[0].headers.contains(&gzip));
+        let record: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(record["short_message"], "raw");
+
+        assert!(requests[1].headers.contains(&gzip));
+        let record: Value = serde_json::from_reader(GzDecoder::new(&*requests[1].body)).unwrap();
+        assert_eq!(record["short_message"], large.as_str());
+    }
+
+    #[test]
+    fn compression_level() {
+        let body = serde_json::to_vec(&serde_json::json!({
+            "version": "1.1",
+            "short_message": "compressed ".repeat(100),
+        }))
+        .unwrap();
+        let fast = compress(&body, 0).unwrap();
+        let best = compress(&body, 9).unwrap();
+        assert!(fast.len() > best.len());
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(&*best).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+
+        // Out of range whatever the target and the order of the calls.
+        assert!(Builder::new()
+            .http(None)
+            .compression_level(10)
+            .build()
+            .is_err());
+        assert!(Builder::new()
+            .compression_level(12)
+            .http(None)
+            .build()
+            .is_err());
+        assert!(Builder::new()
+            .port(12202)
+            .compression_level(10)
+            .build()
+            .is_err());
+
+        // Not an HTTP target, which is left as is.
+        let builder = Builder::new().port(12202).compression_level(9);
+        assert!(builder.describe().starts_with("target: Tcp"));
+        assert!(builder.describe().contains("port: 12202"));
+
+        // Applied to the HTTP target set up afterwards: the stored output is
+        // larger than the record.
+        let (port, server) = serve(1);
+        let logger = Builder::new()
+            .compression_level(0)
+            .target(Target::Http(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                gzip: true,
+                ..HttpTarget::default()
+            }))
+            .build()
+            .unwrap();
+        let large = "compressed ".repeat(100);
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .args(format_args!("{large}"))
+                .build(),
+        );
+        logger.flush();
+
+        let requests = server.join().unwrap();
+        let mut decoded = Vec::new();
+        GzDecoder::new(&*requests[0].body)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(requests[0].body.len() > decoded.len());
+    }
+
+    #[test]
+    fn batch() {
+        let (port, server) = serve(1);
+        let logger = Builder::new()
+            .target(Target::Http(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                batch_size: 10,
+                flush_interval: None,
+                ..HttpTarget::default()
+            }))
+            .build()
+            .unwrap();
+        for message in ["first", "second", "third"] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let requests = server.join().unwrap();
+        let records: Vec<Value> = serde_json::Deserializer::from_slice(&requests[0].body)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2]["short_message"], "third");
+    }
+
+    #[test]
+    fn json_array() {
+        let (port, server) = serve(1);
+        let logger = Builder::new()
+            .http(Some(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                batch_size: 3,
+                batch_format: BatchFormat::JsonArray,
+                ..HttpTarget::default()
+            }))
+            .build()
+            .unwrap();
+        for message in ["first", "second", "third"] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+
+        let requests = server.join().unwrap();
+        let records: Vec<Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["short_message"], "first");
+    }
+
+    #[test]
+    fn join_records() {
+        let records = [b"{}\n".to_vec(), b"[]\n".to_vec()];
+        assert_eq!(join(&records, BatchFormat::NewlineDelimited), b"{}\n[]");
+        assert_eq!(join(&records, BatchFormat::JsonArray), b"[{},[]]");
+        assert_eq!(join(&records[..1], BatchFormat::NewlineDelimited), b"{}");
+        assert_eq!(join(&records[..1], BatchFormat::JsonArray), b"[{}]");
+    }
+
+    #[test]
+    fn authorization() {
+        let (port, server) = serve(1);
+        let logger = Builder::new()
+            .http(Some(HttpTarget {
+                url: format!("http://127.0.0.1:{port}/gelf"),
+                ..HttpTarget::default()
+            }))
+            .http_basic_auth("user".to_owned(), "pass".to_owned())
+            .build()
+            .unwrap();
+        logger.log(&Record::builder().level(Level::Error).build());
+        logger.flush();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0]
+            .headers
+            .contains(&"Authorization: Basic dXNlcjpwYXNz".to_owned()));
+    }
+
+    #[test]
+    fn redacted_headers() {
+        let target = HttpTarget {
+            headers: vec![("Authorization".to_owned(), "Bearer secret".to_owned())],
+            ..HttpTarget::default()
+        };
+        let debug = format!("{target:?}");
+        assert!(debug.contains("Authorization"));
+        assert!(!debug.contains("secret"));
+    }
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+}
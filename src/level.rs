@@ -5,6 +5,8 @@
 use log::Level;
 use serde::{Deserialize, Serialize};
 
+use crate::Error;
+
 /// An enum representing the record level which is equal to the standard syslog
 /// levels.
 #[derive(Serialize, Deserialize, PartialOrd, PartialEq, Copy, Clone, Debug)]
@@ -64,7 +66,23 @@ impl From<Level> for GelfLevel {
     }
 }
 
+#[cfg(feature = "tracing")]
+impl From<tracing_core::Level> for GelfLevel {
+    fn from(level: tracing_core::Level) -> GelfLevel {
+        match level {
+            tracing_core::Level::ERROR => GelfLevel::Error,
+            tracing_core::Level::WARN => GelfLevel::Warning,
+            tracing_core::Level::INFO => GelfLevel::Informational,
+            tracing_core::Level::DEBUG => GelfLevel::Debugging,
+            tracing_core::Level::TRACE => GelfLevel::Debugging,
+        }
+    }
+}
+
 impl From<GelfLevel> for Level {
+    // Inlined for the level checks of the macros to be folded in the calling
+    // crates when the level is a constant.
+    #[inline]
     fn from(level: GelfLevel) -> Self {
         match level {
             GelfLevel::Emergency => Level::Error,
@@ -79,19 +97,11 @@ impl From<GelfLevel> for Level {
     }
 }
 
+/// Values outside of the 0-7 range fall back to `GelfLevel::Alert`, see
+/// [`GelfLevel::try_from_u32`] to reject them instead.
 impl From<u32> for GelfLevel {
     fn from(level: u32) -> Self {
-        match level {
-            0 => GelfLevel::Emergency,
-            1 => GelfLevel::Alert,
-            2 => GelfLevel::Critical,
-            3 => GelfLevel::Error,
-            4 => GelfLevel::Warning,
-            5 => GelfLevel::Notice,
-            6 => GelfLevel::Informational,
-            7 => GelfLevel::Debugging,
-            _ => GelfLevel::Alert,
-        }
+        GelfLevel::try_from_u32(level).unwrap_or_default()
     }
 }
 
@@ -109,3 +119,109 @@ impl From<GelfLevel> for &'static str {
         }
     }
 }
+
+/// The casing of the `_levelname` field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LevelNameStyle {
+    /// E.g. "Informational".
+    #[default]
+    TitleCase,
+    /// E.g. "informational".
+    Lowercase,
+    /// E.g. "INFORMATIONAL".
+    Uppercase,
+    /// The syslog abbreviations, e.g. "info".
+    Short,
+}
+
+impl GelfLevel {
+    /// Converts a syslog severity, failing with [`Error::InvalidLevel`] if it
+    /// is outside of the 0-7 range.
+    pub fn try_from_u32(level: u32) -> Result<GelfLevel, Error> {
+        Ok(match level {
+            0 => GelfLevel::Emergency,
+            1 => GelfLevel::Alert,
+            2 => GelfLevel::Critical,
+            3 => GelfLevel::Error,
+            4 => GelfLevel::Warning,
+            5 => GelfLevel::Notice,
+            6 => GelfLevel::Informational,
+            7 => GelfLevel::Debugging,
+            _ => return Err(Error::InvalidLevel(level)),
+        })
+    }
+
+    /// Returns the name of the level in the given style.
+    pub fn name(self, style: LevelNameStyle) -> &'static str {
+        match style {
+            LevelNameStyle::TitleCase => self.into(),
+            LevelNameStyle::Lowercase => match self {
+                GelfLevel::Emergency => "emergency",
+                GelfLevel::Alert => "alert",
+                GelfLevel::Critical => "critical",
+                GelfLevel::Error => "error",
+                GelfLevel::Warning => "warning",
+                GelfLevel::Notice => "notice",
+                GelfLevel::Informational => "informational",
+                GelfLevel::Debugging => "debugging",
+            },
+            LevelNameStyle::Uppercase => match self {
+                GelfLevel::Emergency => "EMERGENCY",
+                GelfLevel::Alert => "ALERT",
+                GelfLevel::Critical => "CRITICAL",
+                GelfLevel::Error => "ERROR",
+                GelfLevel::Warning => "WARNING",
+                GelfLevel::Notice => "NOTICE",
+                GelfLevel::Informational => "INFORMATIONAL",
+                GelfLevel::Debugging => "DEBUGGING",
+            },
+            LevelNameStyle::Short => match self {
+                GelfLevel::Emergency => "emerg",
+                GelfLevel::Alert => "alert",
+                GelfLevel::Critical => "crit",
+                GelfLevel::Error => "err",
+                GelfLevel::Warning => "warning",
+                GelfLevel::Notice => "notice",
+                GelfLevel::Informational => "info",
+                GelfLevel::Debugging => "debug",
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GelfLevel, LevelNameStyle};
+    use crate::Error;
+
+    #[test]
+    fn name() {
+        let level = GelfLevel::Informational;
+        assert_eq!(level.name(LevelNameStyle::TitleCase), "Informational");
+        assert_eq!(level.name(LevelNameStyle::Lowercase), "informational");
+        assert_eq!(level.name(LevelNameStyle::Uppercase), "INFORMATIONAL");
+        assert_eq!(level.name(LevelNameStyle::Short), "info");
+    }
+
+    #[test]
+    fn try_from_u32() {
+        assert_eq!(GelfLevel::try_from_u32(3).unwrap(), GelfLevel::Error);
+        assert!(matches!(
+            GelfLevel::try_from_u32(8),
+            Err(Error::InvalidLevel(8))
+        ));
+        assert_eq!(GelfLevel::from(8), GelfLevel::Alert);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn from_tracing() {
+        use tracing_core::Level;
+
+        assert_eq!(GelfLevel::from(Level::ERROR), GelfLevel::Error);
+        assert_eq!(GelfLevel::from(Level::WARN), GelfLevel::Warning);
+        assert_eq!(GelfLevel::from(Level::INFO), GelfLevel::Informational);
+        assert_eq!(GelfLevel::from(Level::DEBUG), GelfLevel::Debugging);
+        assert_eq!(GelfLevel::from(Level::TRACE), GelfLevel::Debugging);
+    }
+}
@@ -46,6 +46,11 @@
 //! info!(req:?; "incoming request");
 //! // Will flatten all the field and add them as additional fields.
 //! info!(req:serde; "incoming request");
+//! // Maps and sequences are flattened the same way, as `_ctx_k1` and `_ctx_k2`
+//! // here, be they captured with `:serde` or given as a `log::kv::Value`.
+//! let ctx = std::collections::HashMap::from([("k1", "a"), ("k2", "b")]);
+//! info!(ctx:serde = ctx; "incoming request");
+//! info!(ctx = log::kv::Value::from_serde(&ctx); "incoming request");
 //!
 //! // Gelf specific levels.
 //! gelf_log!(GelfLevel::Emergency, foo = "bar"; "an emergency log");
@@ -69,7 +74,7 @@
 #![warn(
     clippy::all,
     clippy::todo,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::enum_glob_use,
     clippy::mem_forget,
     clippy::unused_self,
@@ -78,9 +83,7 @@
     clippy::needless_borrow,
     clippy::match_wildcard_for_single_variants,
     clippy::if_let_mutex,
-    clippy::mismatched_target_os,
     clippy::await_holding_lock,
-    clippy::match_on_vec_items,
     clippy::imprecise_flops,
     clippy::suboptimal_flops,
     clippy::lossy_float_literal,
@@ -105,19 +108,45 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 #![cfg_attr(not(test), warn(clippy::print_stdout, clippy::dbg_macro))]
 
+#[cfg(feature = "tokio")]
+mod async_logger;
 mod builder;
+mod context;
 mod error;
+#[cfg(feature = "http")]
+mod http;
 mod level;
 mod logger;
 mod macros;
+mod memory;
+mod non_finite;
+mod panic;
 mod record;
+mod spill;
+mod stats;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+mod tls;
 
+#[cfg(feature = "tokio")]
+pub use async_logger::{AsyncGelfLogger, LogBridge};
 pub use builder::Builder;
+pub use context::{set_trace_context, TraceContextGuard};
 pub use error::Error;
-pub use level::GelfLevel;
-pub use logger::{GelfLogger, Target, TcpTarget};
-pub use record::GelfRecord;
+#[cfg(feature = "http")]
+pub use http::{BatchFormat, HttpTarget};
+pub use level::{GelfLevel, LevelNameStyle};
+pub use logger::{
+    send_blocking, ConnectionFactory, Framing, FullBufferPolicy, GelfHandle, GelfLogger, Target,
+    TcpTarget, WriteTimeoutPolicy,
+};
+pub use memory::MemoryHandle;
+pub use panic::set_panic_hook;
+pub use record::{
+    CollisionPolicy, DurationUnit, GelfRecord, NonFiniteFloatPolicy, NullFieldPolicy,
+};
 #[doc(hidden)]
-pub use record::INTERNAL_LEVEL_FIELD_NAME;
+pub use record::{INTERNAL_FIELDS_FIELD_NAME, INTERNAL_LEVEL_FIELD_NAME};
 #[doc(no_inline)]
 pub use serde_json::{Map, Value};
+pub use spill::SpillFile;
+pub use stats::Stats;
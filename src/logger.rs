@@ -3,27 +3,65 @@
 // Copyright 2024 The gelf_logger Authors. All rights reserved.
 
 use std::{
-    io,
-    io::Write,
-    net::{TcpStream, ToSocketAddrs},
-    sync::mpsc,
+    fmt, io,
+    io::{BufWriter, Write},
+    iter,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, PoisonError,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use env_filter::Filter;
-use log::{LevelFilter, Log, Metadata, Record};
-use native_tls::{TlsConnector, TlsStream};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::{Builder, Error, GelfRecord, Map, Value};
+#[cfg(feature = "http")]
+use crate::http;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use crate::tls;
+use crate::{
+    memory::{MemoryHandle, Recent},
+    record::{self, LeafOptions, RecordOptions},
+    spill::{Spill, SpillFile},
+    stats::{Counters, Stats},
+    Builder, Error, GelfLevel, GelfRecord, Map, Value,
+};
 
 /// A logger that will format and forward any [`Record`] to the set-up target.
-#[derive(Debug)]
+///
+/// Cloning a logger is cheap: every clone shares the same underlying writer
+/// and background thread.
+///
+/// A record with a `__gelf_flush` key set to `true` is flushed right after
+/// being queued. A `__gelf_timestamp` key, in seconds since UNIX epoch,
+/// overrides the `timestamp` of the record, e.g. for replayed events. These
+/// keys are not sent as fields.
+#[derive(Clone, Debug)]
 pub struct GelfLogger {
     pub(crate) filter: Filter,
-    pub(crate) writer: Writer,
+    pub(crate) max_gelf_level: Option<GelfLevel>,
+    pub(crate) writer: Arc<Writer>,
+    pub(crate) routes: Vec<(String, Arc<Writer>)>,
+    pub(crate) fallback: Option<Arc<Writer>>,
+    pub(crate) newline: bool,
     pub(crate) null_character: bool,
     pub(crate) additional_fields: Map<String, Value>,
+    pub(crate) target_raw_additional_fields: Vec<(String, Map<String, Value>)>,
+    pub(crate) dynamic_fields: Vec<(String, fn() -> Value)>,
+    pub(crate) type_suffix: bool,
+    pub(crate) record_options: RecordOptions,
+    pub(crate) sequence: Option<Arc<AtomicU64>>,
+    pub(crate) drop_flush_timeout: Option<Duration>,
+    pub(crate) drop_report_interval: Option<Duration>,
+    pub(crate) flush_on_drop: bool,
+    pub(crate) strict: bool,
+    pub(crate) sort_fields: bool,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) recent: Option<Arc<Recent>>,
 }
 
 impl GelfLogger {
@@ -40,9 +78,160 @@ impl GelfLogger {
         self.filter.filter()
     }
 
-    /// Checks if this record matches the configured filter.
+    /// Checks if this record matches the configured filter and GELF level
+    /// threshold.
     pub fn matches(&self, record: &Record<'_>) -> bool {
         self.filter.matches(record)
+            && self
+                .max_gelf_level
+                .is_none_or(|max| record::gelf_level(record) <= max)
+    }
+
+    /// Returns the most recent records, oldest first, if enabled with
+    /// [`Builder::keep_last`]. They are kept even if they couldn't be sent.
+    pub fn recent(&self) -> Vec<GelfRecord<'static>> {
+        self.recent
+            .as_ref()
+            .map(|recent| recent.records())
+            .unwrap_or_default()
+    }
+
+    /// Returns a snapshot of the counters shared by every clone of the logger.
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
+    }
+
+    /// Render the counters of the logger in the Prometheus text exposition
+    /// format, to be served from an HTTP handler.
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus_metrics(&self) -> String {
+        self.counters.prometheus()
+    }
+
+    /// Flush the queued records like [`Log::flush`] but give up once the
+    /// timeout is reached instead of blocking indefinitely, e.g. when the
+    /// background thread can't reach the remote host.
+    ///
+    /// Returns `true` if every queued record has been written and flushed.
+    pub fn flush_timeout(&self, timeout: Duration) -> bool {
+        self.flush_until(Some(Instant::now() + timeout))
+    }
+
+    /// Serialize and forward an already built [`GelfRecord`] to the target.
+    ///
+    /// The record is sent as is: neither the filter nor the additional fields
+    /// set up on the [`Builder`] are applied.
+    pub fn log_record(&self, record: &GelfRecord<'_>) {
+        if self.writer.measures_queue_latency() {
+            let mut record = record.clone();
+            stamp_queued_at(&mut record);
+            self.write_record(&self.writer, &record);
+        } else {
+            self.write_record(&self.writer, record);
+        }
+    }
+
+    fn write_record(&self, writer: &Writer, record: &GelfRecord<'_>) {
+        if let Some(recent) = &self.recent {
+            recent.push(record.clone().into_owned());
+        }
+        if let Writer::Memory(memory) = writer {
+            memory.push(record.clone().into_owned());
+            return;
+        }
+
+        self.report_drops(writer);
+        if let Some(data) = self.encode(record) {
+            if !writer.write(Op::Data(data)) {
+                self.count_drops(1);
+            }
+        }
+    }
+
+    /// Serialize and forward already built [`GelfRecord`]s to the target at
+    /// once, e.g. when importing events from another source. They are queued
+    /// to a single background thread with one channel operation, to which the
+    /// full buffer policy applies, and are kept or dropped together.
+    ///
+    /// The records are sent as is, like with [`GelfLogger::log_record`].
+    pub fn log_many<'a>(&self, records: impl IntoIterator<Item = GelfRecord<'a>>) {
+        let mut batch = Vec::new();
+        for mut record in records {
+            if self.writer.measures_queue_latency() {
+                stamp_queued_at(&mut record);
+            }
+            if let Some(recent) = &self.recent {
+                recent.push(record.clone().into_owned());
+            }
+            match &*self.writer {
+                Writer::Memory(memory) => memory.push(record.into_owned()),
+                _ => batch.extend(self.encode(&record)),
+            }
+        }
+        if batch.is_empty() {
+            return;
+        }
+
+        self.report_drops(&self.writer);
+        let len = batch.len() as u64;
+        if !self.writer.write(Op::Batch(batch)) {
+            self.count_drops(len);
+        }
+    }
+
+    /// Count records dropped because the background buffer was full.
+    fn count_drops(&self, count: u64) {
+        self.counters.dropped.fetch_add(count, Ordering::Relaxed);
+        self.counters
+            .unreported_drops
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Send a warning record summarizing the records dropped since the last
+    /// one, unless one was sent less than the report interval ago.
+    fn report_drops(&self, writer: &Writer) {
+        let Some(interval) = self.drop_report_interval else {
+            return;
+        };
+        let counters = &self.counters;
+        if counters.unreported_drops.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as u64);
+        let last = counters.last_drop_report.load(Ordering::Relaxed);
+        if now.saturating_sub(last) < interval.as_millis() as u64
+            || counters
+                .last_drop_report
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+
+        let dropped = counters.unreported_drops.swap(0, Ordering::Relaxed);
+        let kvs = [("dropped", dropped)];
+        let data = self
+            .prepare(
+                &Record::builder()
+                    .args(format_args!("{dropped} records dropped"))
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .key_values(&kvs)
+                    .build(),
+                writer,
+            )
+            .ok()
+            .and_then(|summary| self.encode(&summary));
+        let sent = data.is_some_and(|data| writer.write(Op::Data(data)));
+        if !sent {
+            // Retry with the next record rather than after another interval.
+            counters
+                .unreported_drops
+                .fetch_add(dropped, Ordering::Relaxed);
+            counters.last_drop_report.store(last, Ordering::Relaxed);
+        }
     }
 }
 
@@ -56,33 +245,262 @@ impl Log for GelfLogger {
             return;
         }
 
-        let mut record = GelfRecord::from(record);
+        let writer = match record::route(record) {
+            Some(route) => self
+                .routes
+                .iter()
+                .find(|(name, _)| *name == route)
+                .map_or(&self.writer, |(_, writer)| writer),
+            None => &self.writer,
+        };
+        let writer = match &self.fallback {
+            Some(fallback) if writer.is_down() => fallback,
+            _ => writer,
+        };
+        match self.prepare(record, writer) {
+            Ok(record) => self.write_record(writer, &record),
+            Err(err) => {
+                self.handle_error(err);
+                return;
+            }
+        }
+        if record::flush_requested(record) {
+            writer.flush_until(None);
+        }
+    }
+
+    fn flush(&self) {
+        self.flush_until(None);
+    }
+}
+
+impl GelfLogger {
+    /// Build the record to send to the writer, with the additional fields set
+    /// up on the [`Builder`]. Fails if the record is invalid in strict mode.
+    pub(crate) fn prepare<'a>(
+        &self,
+        record: &'a Record<'_>,
+        writer: &Writer,
+    ) -> Result<GelfRecord<'a>, Error> {
+        let target = record.target();
+        let (mut record, failures) = GelfRecord::from_record_checked(record, &self.record_options);
+        if let Some((count, err)) = failures {
+            self.report_serialization_error(count, err);
+        }
         record
             .additional_fields
             .extend(self.additional_fields.clone());
+        if let Some((_, fields)) = self
+            .target_raw_additional_fields
+            .iter()
+            .filter(|(prefix, _)| is_submodule(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+        {
+            record.additional_fields.extend(fields.clone());
+        }
+        if !self.dynamic_fields.is_empty() {
+            let fields = self
+                .dynamic_fields
+                .iter()
+                .map(|(key, f)| (key.clone(), f()))
+                .collect();
+            let leaf = LeafOptions {
+                transform: None,
+                ..self.record_options.leaf_options()
+            };
+            record.additional_fields.extend(record::flatten_with(
+                fields,
+                Some("_"),
+                self.record_options.separator(),
+                self.type_suffix,
+                leaf,
+            ));
+        }
+        if let Some(sequence) = &self.sequence {
+            record.additional_fields.insert(
+                "_seq".to_owned(),
+                Value::from(sequence.fetch_add(1, Ordering::Relaxed)),
+            );
+        }
+        if writer.measures_queue_latency() {
+            stamp_queued_at(&mut record);
+        }
+        // The fields added as is, without being flattened.
+        for name in &self.record_options.redacted_fields {
+            if let Some(value) = record.additional_fields.get_mut(name) {
+                *value = Value::from("***");
+            }
+        }
+        if self.sort_fields {
+            record.additional_fields.sort_keys();
+        }
+        if self.strict {
+            if let Err(reason) = record.validate() {
+                self.counters.invalid.fetch_add(1, Ordering::Relaxed);
+                return Err(Error::InvalidRecord(reason));
+            }
+        }
+        Ok(record)
+    }
 
-        let Ok(mut data) = serde_json::to_vec(&record) else {
-            return;
+    /// Serialize the record, followed by the configured delimiters.
+    pub(crate) fn encode(&self, record: &GelfRecord<'_>) -> Option<Vec<u8>> {
+        let mut data = match record.to_json() {
+            Ok(data) => data,
+            Err(err) => {
+                self.report_serialization_error(1, err);
+                return None;
+            }
         };
-
-        data.push(b'\n');
+        if self.newline {
+            data.push(b'\n');
+        }
         if self.null_character {
             data.push(b'\0');
         }
+        Some(data)
+    }
 
-        self.writer.write(Op::Data(data));
+    /// Count the fields or records that couldn't be serialized and pass the
+    /// error to the background error handler.
+    fn report_serialization_error(&self, count: u64, err: io::Error) {
+        self.counters
+            .serialization_errors
+            .fetch_add(count, Ordering::Relaxed);
+        self.handle_error(Error::Serialization(err));
     }
 
-    fn flush(&self) {
-        let (tx, rx) = mpsc::sync_channel(1);
-        self.writer.write(Op::Flush(tx));
-        let _ = rx.recv();
+    /// Pass the error to the background error handler of the main target, if
+    /// any.
+    fn handle_error(&self, err: Error) {
+        if let Writer::Pipe(pipe) = &*self.writer {
+            handle_background_error::<(), _>(pipe.background_error_handler, Err(err));
+        }
+    }
+
+    /// Establish the connections to the remote hosts right away instead of on
+    /// the first record, e.g. to check the configuration after [`init`].
+    /// Returns the first error raised by one of the targets.
+    ///
+    /// It blocks until the connections are established or fail, once the
+    /// queued records are written.
+    ///
+    /// [`init`]: Builder::init
+    pub fn connect(&self) -> Result<(), Error> {
+        iter::once(&self.writer)
+            .chain(self.routes.iter().map(|(_, writer)| writer))
+            .chain(&self.fallback)
+            .try_for_each(|writer| writer.connect())
+    }
+
+    /// Wait for every queued record to be written and flushed, giving up once
+    /// the deadline, if any, is reached.
+    fn flush_until(&self, deadline: Option<Instant>) -> bool {
+        // Every writer is flushed, even once one failed.
+        iter::once(&self.writer)
+            .chain(self.routes.iter().map(|(_, writer)| writer))
+            .chain(&self.fallback)
+            .filter(|writer| !writer.flush_until(deadline))
+            .count()
+            == 0
+    }
+}
+
+impl Writer {
+    /// Wait for every queued record to be written and flushed, giving up once
+    /// the deadline, if any, is reached.
+    fn flush_until(&self, deadline: Option<Instant>) -> bool {
+        let acks = self.flush_acks();
+        let (tx, rx) = mpsc::sync_channel(acks);
+        if !self.write_until(Op::Flush(tx), deadline) {
+            return false;
+        }
+        (0..acks).all(|_| match deadline {
+            Some(deadline) => rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .is_ok(),
+            None => rx.recv().is_ok(),
+        })
+    }
+
+    /// Whether the records are stamped with `_queued_at` and `_sent_at`.
+    fn measures_queue_latency(&self) -> bool {
+        matches!(self, Writer::Pipe(pipe) if pipe.measure_queue_latency)
+    }
+
+    /// Whether every background thread failed to reach the remote host.
+    fn is_down(&self) -> bool {
+        match self {
+            Writer::Pipe(pipe) => pipe
+                .lanes
+                .iter()
+                .all(|lane| lane.shared.down.load(Ordering::Relaxed)),
+            _ => false,
+        }
+    }
+
+    /// Let the background threads reconnect on their own while down, as they
+    /// no longer receive records to trigger it.
+    pub(crate) fn probe_when_down(&self) {
+        if let Writer::Pipe(pipe) = self {
+            for lane in &pipe.lanes {
+                lane.shared.probe.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A handle controlling the lifecycle of a [`GelfLogger`] built with
+/// [`Builder::build_handle`], without access to the logging methods.
+#[derive(Clone, Debug)]
+pub struct GelfHandle {
+    logger: GelfLogger,
+}
+
+impl GelfHandle {
+    /// Flush the queued records, blocking until they are written.
+    pub fn flush(&self) {
+        self.logger.flush();
+    }
+
+    /// Flush the queued records, see [`GelfLogger::flush_timeout`].
+    pub fn flush_timeout(&self, timeout: Duration) -> bool {
+        self.logger.flush_timeout(timeout)
+    }
+
+    /// Returns a snapshot of the counters of the logger.
+    pub fn stats(&self) -> Stats {
+        self.logger.stats()
+    }
+
+    /// Flush the queued records, giving up once the timeout is reached, and
+    /// release the handle. The background threads stop once the logger and
+    /// all its clones are dropped too.
+    ///
+    /// Returns `true` if every queued record has been written and flushed.
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        self.logger.flush_timeout(timeout)
+    }
+}
+
+impl GelfLogger {
+    pub(crate) fn handle(&self) -> GelfHandle {
+        GelfHandle {
+            logger: self.clone(),
+        }
     }
 }
 
 impl Drop for GelfLogger {
+    /// Flush the queued records when the last clone of the logger is dropped,
+    /// unless disabled with [`Builder::flush_on_drop`].
     fn drop(&mut self) {
-        self.flush();
+        if self.flush_on_drop && Arc::strong_count(&self.writer) == 1 {
+            self.flush_until(
+                self.drop_flush_timeout
+                    .map(|timeout| Instant::now() + timeout),
+            );
+        }
     }
 }
 
@@ -90,217 +508,1162 @@ impl Drop for GelfLogger {
 pub(crate) enum Writer {
     Stdout,
     Stderr,
-    Pipe(mpsc::SyncSender<Op>),
+    Memory(MemoryHandle),
+    Pipe(Pipe),
+    #[cfg(feature = "http")]
+    Http(Sender),
+    /// Drops the serialized records, see [`Builder::dry_run`].
+    Discard,
 }
 
 impl Writer {
-    pub(crate) fn new(target: Target) -> Result<Self, Error> {
+    pub(crate) fn new(target: Target, counters: &Arc<Counters>) -> Result<Self, Error> {
         Ok(match target {
             Target::Stdout => Self::Stdout,
             Target::Stderr => Self::Stderr,
-            Target::Tcp(TcpTarget {
-                hostname,
-                port,
-                tls,
-                connect_timeout,
-                write_timeout,
-                buffer_size,
-                background_error_handler,
-            }) => {
-                let (tx, rx) = mpsc::sync_channel::<Op>(buffer_size);
-                thread::spawn(move || {
-                    let mut conn = None;
-                    while let Ok(op) = rx.recv() {
-                        if conn.is_none() {
-                            conn = handle_background_error(
-                                background_error_handler,
-                                TcpConnection::new(
-                                    &hostname,
-                                    port,
-                                    tls,
-                                    connect_timeout,
-                                    write_timeout,
-                                ),
-                            );
+            Target::Memory(memory) => Self::Memory(memory),
+            Target::Tcp(target) => {
+                let spill = match &target.spill_file {
+                    Some(config) => Some(Arc::new(Spill::open(config)?)),
+                    None => None,
+                };
+                let lanes = (0..target.worker_count.max(1))
+                    .map(|_| {
+                        let shared = Shared::default();
+                        if let Some((retries, backoff)) = target.connect_retries {
+                            *shared.conn.lock().unwrap_or_else(PoisonError::into_inner) =
+                                Some(TcpConnection::with_retries(&target, retries, backoff)?);
                         }
-
-                        if let Some(conn_ref) = &mut conn {
-                            match op {
-                                Op::Data(data) => {
-                                    if handle_background_error(
-                                        background_error_handler,
-                                        conn_ref.write_all(&data),
-                                    )
-                                    .is_none()
-                                    {
-                                        conn = None;
-                                    }
-                                }
-                                Op::Flush(tx) => {
-                                    if handle_background_error(
-                                        background_error_handler,
-                                        conn_ref.flush(),
-                                    )
-                                    .is_none()
-                                    {
-                                        conn = None;
-                                    }
-                                    let _ = tx.send(());
-                                }
-                            }
+                        let (tx, rx) = Sender::channel(
+                            target.buffer_size,
+                            target.unbounded || target.buffer_bytes.is_some(),
+                        );
+                        let worker = Worker {
+                            target: target.clone(),
+                            shared: Arc::new(shared),
+                            spill: spill.clone(),
+                            counters: counters.clone(),
+                        };
+                        let lane = Lane {
+                            tx,
+                            shared: worker.shared.clone(),
+                            budget: target.buffer_bytes,
+                        };
+                        let mut thread = thread::Builder::new().name(WORKER_NAME.to_owned());
+                        if let Some(size) = target.worker_stack_size {
+                            thread = thread.stack_size(size);
                         }
-                    }
-                });
-                Self::Pipe(tx)
+                        thread.spawn(move || worker.run(rx))?;
+                        Ok(lane)
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Self::Pipe(Pipe {
+                    lanes,
+                    next: AtomicUsize::new(0),
+                    policy: target.full_buffer_policy,
+                    inline: target.inline_when_idle,
+                    background_error_handler: target.background_error_handler,
+                    spill,
+                    framing: target.framing,
+                    measure_queue_latency: target.measure_queue_latency,
+                    counters: counters.clone(),
+                })
+            }
+            #[cfg(feature = "http")]
+            Target::Http(target) => {
+                let (tx, rx) = Sender::channel(target.buffer_size, false);
+                let worker = http::Worker::new(target)?;
+                thread::Builder::new()
+                    .name(WORKER_NAME.to_owned())
+                    .spawn(move || worker.run(rx))?;
+                Self::Http(tx)
             }
         })
     }
 
-    fn write(&self, op: Op) {
+    /// The number of acknowledgements sent back for a flush operation.
+    fn flush_acks(&self) -> usize {
+        match self {
+            Writer::Pipe(pipe) => pipe.lanes.len(),
+            _ => 1,
+        }
+    }
+
+    /// Write the operation, applying the full buffer policy to records.
+    /// Returns whether the operation was accepted.
+    fn write(&self, op: Op) -> bool {
+        let deadline = match (self, &op) {
+            (Writer::Pipe(pipe), Op::Data(_) | Op::Batch(_)) => pipe.policy.deadline(),
+            _ => None,
+        };
+        self.write_until(op, deadline)
+    }
+
+    /// Write the operation, waiting until the deadline at most if the
+    /// background buffer is full. Returns whether the operation was accepted.
+    fn write_until(&self, op: Op, deadline: Option<Instant>) -> bool {
         match op {
             Op::Data(data) => match self {
-                Writer::Stdout => {
-                    let _ = io::stdout().write_all(&data);
-                }
-                Writer::Stderr => {
-                    let _ = io::stderr().write_all(&data);
-                }
-                Writer::Pipe(tx) => {
-                    let _ = tx.send(Op::Data(data));
-                }
+                Writer::Stdout => io::stdout().write_all(&data).is_ok(),
+                Writer::Stderr => io::stderr().write_all(&data).is_ok(),
+                Writer::Memory(_) | Writer::Discard => true,
+                Writer::Pipe(pipe) => pipe.send_until(Op::Data(data), deadline),
+                #[cfg(feature = "http")]
+                Writer::Http(tx) => tx.send_until(Op::Data(data), deadline).is_ok(),
+            },
+            Op::Batch(records) => match self {
+                Writer::Stdout => io::stdout().write_all(&records.concat()).is_ok(),
+                Writer::Stderr => io::stderr().write_all(&records.concat()).is_ok(),
+                Writer::Memory(_) | Writer::Discard => true,
+                Writer::Pipe(pipe) => pipe.send_until(Op::Batch(records), deadline),
+                #[cfg(feature = "http")]
+                Writer::Http(tx) => tx.send_until(Op::Batch(records), deadline).is_ok(),
             },
             Op::Flush(flush_tx) => match self {
                 Writer::Stdout => {
                     let _ = io::stdout().flush();
-                    let _ = flush_tx.send(());
+                    flush_tx.send(()).is_ok()
                 }
                 Writer::Stderr => {
                     let _ = io::stderr().flush();
-                    let _ = flush_tx.send(());
-                }
-                Writer::Pipe(tx) => {
-                    let _ = tx.send(Op::Flush(flush_tx));
+                    flush_tx.send(()).is_ok()
                 }
+                Writer::Memory(_) | Writer::Discard => flush_tx.send(()).is_ok(),
+                Writer::Pipe(pipe) => pipe.send_until(Op::Flush(flush_tx), deadline),
+                #[cfg(feature = "http")]
+                Writer::Http(tx) => tx.send_until(Op::Flush(flush_tx), deadline).is_ok(),
+            },
+            Op::Connect(connect_tx) => match self {
+                Writer::Pipe(pipe) => pipe.send_until(Op::Connect(connect_tx), deadline),
+                _ => connect_tx.send(Ok(())).is_ok(),
             },
         }
     }
-}
-
-pub(crate) enum Op {
-    Data(Vec<u8>),
-    Flush(mpsc::SyncSender<()>),
-}
 
-/// The output target used by a [`GelfLogger`].
-#[derive(Clone, Debug)]
-pub enum Target {
-    /// GELF records will be printed to stdout.
-    Stdout,
-    /// GELF records will be printed to stderr.
-    Stderr,
-    /// GELF records will be forwarded over TCP.
-    Tcp(TcpTarget),
+    /// Establish the connections of the background threads, if not already,
+    /// waiting for the outcome.
+    fn connect(&self) -> Result<(), Error> {
+        let acks = self.flush_acks();
+        let (tx, rx) = mpsc::sync_channel(acks);
+        let stopped = || Error::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        if !self.write_until(Op::Connect(tx), None) {
+            return Err(stopped());
+        }
+        (0..acks).try_for_each(|_| rx.recv().map_err(|_| stopped())?)
+    }
 }
 
-/// A TCP target used to send the GELF records.
-#[derive(Clone, Debug)]
-pub struct TcpTarget {
-    /// The hostname used to resolve the remote host and establish the TLS
-    /// handshake if requested.
-    pub hostname: String,
-    /// The remote port to connect to.
-    pub port: u16,
-    /// Whether to use TLS over TCP. The hostname specified above will be used
-    /// to perform the TLS handshake.
-    pub tls: bool,
-    /// Set the connection timeout duration. If `None` is specified, the socket
-    /// connection phase can block indefinitely.
-    pub connect_timeout: Option<Duration>,
-    /// Set the connection write timeout duration. If `None` is specified, the
-    /// socket write calls can block indefinitely.
-    pub write_timeout: Option<Duration>,
-    /// Set the number of messages that can be queued between the caller and
-    /// background threads. If too many log calls are made and the background is
-    /// too slow, this buffer will fill up. When full, calls on the current
-    /// thread will start to block.
-    pub buffer_size: usize,
-    /// Register a static function that will be called when errors occur in the
-    /// background thread.
-    pub background_error_handler: Option<fn(Error)>,
+/// The caller side of the background threads. Records are dispatched to them
+/// in turn, and flushes to all of them.
+#[derive(Debug)]
+pub(crate) struct Pipe {
+    lanes: Vec<Lane>,
+    next: AtomicUsize,
+    policy: FullBufferPolicy,
+    inline: bool,
+    background_error_handler: Option<fn(Error)>,
+    spill: Option<Arc<Spill>>,
+    framing: Framing,
+    measure_queue_latency: bool,
+    counters: Arc<Counters>,
 }
 
-impl Default for TcpTarget {
-    /// Crate TCP target with the following placeholders:
-    /// ```rust,ignore
-    /// TcpTarget {
-    ///     hostname: "127.0.0.1".to_owned(),
-    ///     port: 2202,
-    ///     tls: false,
-    ///     connect_timeout: None,
-    ///     write_timeout: None,
-    ///     buffer_size: 1_000,
-    ///     background_error_handler: None,
-    /// }
-    /// ```
-    fn default() -> Self {
+impl Pipe {
+    #[cfg(test)]
+    fn new(tx: Sender, policy: FullBufferPolicy) -> Self {
         Self {
-            hostname: "127.0.0.1".to_owned(),
-            port: 2202,
-            tls: false,
-            connect_timeout: None,
-            write_timeout: None,
-            buffer_size: 1_000,
+            lanes: vec![Lane {
+                tx,
+                shared: Arc::default(),
+                budget: None,
+            }],
+            next: AtomicUsize::new(0),
+            policy,
+            inline: false,
             background_error_handler: None,
+            spill: None,
+            framing: Framing::default(),
+            measure_queue_latency: false,
+            counters: Arc::default(),
+        }
+    }
+
+    fn send_until(&self, op: Op, deadline: Option<Instant>) -> bool {
+        let data = match op {
+            Op::Data(data) => data,
+            Op::Batch(records) => return self.send_batch(records, deadline),
+            Op::Flush(tx) => {
+                return self
+                    .lanes
+                    .iter()
+                    .all(|lane| lane.send_until(Op::Flush(tx.clone()), deadline).is_ok());
+            }
+            Op::Connect(tx) => {
+                return self
+                    .lanes
+                    .iter()
+                    .all(|lane| lane.send_until(Op::Connect(tx.clone()), deadline).is_ok());
+            }
+        };
+
+        let lane = &self.lanes[self.next.fetch_add(1, Ordering::Relaxed) % self.lanes.len()];
+        let mut data = self.framing.frame(data);
+        if self.inline {
+            match self.write_inline(lane, data) {
+                Ok(()) => return true,
+                Err(back) => data = back,
+            }
+        }
+        match (lane.send_until(Op::Data(data), deadline), &self.spill) {
+            (Ok(()), _) => true,
+            (Err(Op::Data(data)), Some(spill)) => spill.push(&data),
+            (Err(_), _) => false,
+        }
+    }
+
+    /// Queue the records to a single background thread at once, keeping their
+    /// order.
+    fn send_batch(&self, records: Vec<Vec<u8>>, deadline: Option<Instant>) -> bool {
+        let lane = &self.lanes[self.next.fetch_add(1, Ordering::Relaxed) % self.lanes.len()];
+        let records = records
+            .into_iter()
+            .map(|data| self.framing.frame(data))
+            .collect();
+        match (lane.send_until(Op::Batch(records), deadline), &self.spill) {
+            (Ok(()), _) => true,
+            (Err(Op::Batch(records)), Some(spill)) => records.iter().all(|data| spill.push(data)),
+            (Err(_), _) => false,
+        }
+    }
+
+    /// Write the record on the current thread if the connection is established
+    /// and no operation is waiting for the background thread, so that records
+    /// are never reordered. Gives the record back otherwise.
+    fn write_inline(&self, lane: &Lane, data: Vec<u8>) -> Result<(), Vec<u8>> {
+        let Ok(mut conn) = lane.shared.conn.try_lock() else {
+            return Err(data);
+        };
+        if lane.shared.pending.load(Ordering::SeqCst) != 0 {
+            return Err(data);
+        }
+        let Some(conn_ref) = conn.as_mut() else {
+            return Err(data);
+        };
+        let data = if self.measure_queue_latency {
+            stamp(data, "_sent_at")
+        } else {
+            data
+        };
+        let result = conn_ref.write_all(&data);
+        if result.is_ok() {
+            self.counters.report_sent();
+        } else {
+            self.counters.report(false);
         }
+        if handle_background_error(self.background_error_handler, result).is_none() {
+            *conn = None;
+        }
+        Ok(())
     }
 }
 
-enum TcpConnection {
-    Raw(TcpStream),
-    Tls(TlsStream<TcpStream>),
+/// The channel to a background thread.
+#[derive(Debug)]
+struct Lane {
+    tx: Sender,
+    shared: Arc<Shared>,
+    /// The maximum size in bytes of the queued records, if any.
+    budget: Option<usize>,
 }
 
-impl TcpConnection {
-    fn new(
-        hostname: &str,
-        port: u16,
-        tls: bool,
-        connect_timeout: Option<Duration>,
-        write_timeout: Option<Duration>,
-    ) -> Result<Self, Error> {
-        let socket_addr = (hostname, port).to_socket_addrs().unwrap().next().unwrap();
-        let stream = match connect_timeout {
-            Some(timeout) => TcpStream::connect_timeout(&socket_addr, timeout),
-            None => TcpStream::connect(socket_addr),
-        }?;
-        stream.set_write_timeout(write_timeout)?;
+impl Lane {
+    /// Send the operation, keeping track of the pending ones. The operation is
+    /// given back if it couldn't be sent.
+    fn send_until(&self, op: Op, deadline: Option<Instant>) -> Result<(), Op> {
+        let len = op.len();
+        if !self.reserve(len, deadline) {
+            return Err(op);
+        }
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        let result = self.tx.send_until(op, deadline);
+        if result.is_err() {
+            self.shared.pending.fetch_sub(1, Ordering::SeqCst);
+            self.shared.release(len);
+        }
+        result
+    }
 
-        Ok(if tls {
-            let connector = TlsConnector::new()?;
-            Self::Tls(connector.connect(hostname, stream)?)
-        } else {
-            Self::Raw(stream)
+    /// Account for `len` more queued bytes, waiting until the deadline at most
+    /// for them to fit in the budget. Returns whether they were accounted for.
+    fn reserve(&self, len: usize, deadline: Option<Instant>) -> bool {
+        let queued = &self.shared.queued_bytes;
+        let Some(budget) = self.budget else {
+            queued.fetch_add(len, Ordering::SeqCst);
+            return true;
+        };
+        self.shared.released.wait_until(deadline, || {
+            queued
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                    (current == 0 || current + len <= budget).then_some(current + len)
+                })
+                .is_ok()
         })
     }
+}
 
-    fn write_all(&mut self, data: &[u8]) -> Result<(), io::Error> {
-        match self {
-            TcpConnection::Raw(stream) => stream.write_all(data),
-            TcpConnection::Tls(stream) => stream.write_all(data),
+/// The connection state shared between the background thread and the caller
+/// threads writing inline.
+#[derive(Debug, Default)]
+struct Shared {
+    conn: Mutex<Option<TcpConnection>>,
+    /// The number of operations sent to the background thread but not yet
+    /// processed.
+    pending: AtomicUsize,
+    /// The total size in bytes of the records sent to the background thread
+    /// but not yet processed.
+    queued_bytes: AtomicUsize,
+    /// Notified when queued bytes are released.
+    released: Signal,
+    /// Whether the last connection operation failed.
+    down: AtomicBool,
+    /// Whether the background thread reconnects on its own while down, as
+    /// records are then sent to the fallback target.
+    probe: AtomicBool,
+}
+
+impl Shared {
+    /// Account for `len` less queued bytes, waking up the callers waiting for
+    /// the byte budget.
+    fn release(&self, len: usize) {
+        self.queued_bytes.fetch_sub(len, Ordering::SeqCst);
+        if len != 0 {
+            self.released.notify();
         }
     }
+}
 
-    fn flush(&mut self) -> Result<(), io::Error> {
-        match self {
-            TcpConnection::Raw(stream) => stream.flush(),
-            TcpConnection::Tls(stream) => stream.flush(),
+/// The background thread of a TCP target.
+struct Worker {
+    target: TcpTarget,
+    shared: Arc<Shared>,
+    spill: Option<Arc<Spill>>,
+    counters: Arc<Counters>,
+}
+
+impl Worker {
+    fn run(self, rx: Receiver) {
+        let mut connected_at: Option<Instant> = None;
+        loop {
+            let probe = self.shared.probe.load(Ordering::Relaxed)
+                && self.shared.down.load(Ordering::Relaxed);
+            let op = match probe {
+                true => rx.recv_timeout(PROBE_INTERVAL),
+                false => rx.recv().map_err(mpsc::RecvTimeoutError::from),
+            };
+            let mut conn = self
+                .shared
+                .conn
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let op = match op {
+                Ok(op) => op,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if conn.is_none() {
+                        *conn = self.connect();
+                    }
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            if conn.is_none() {
+                connected_at = None;
+            }
+            if let (Some(conn_ref), Some(connected_at), Some(max_age)) =
+                (conn.as_mut(), connected_at, self.target.max_connection_age)
+            {
+                if connected_at.elapsed() >= max_age {
+                    let _ = conn_ref.flush();
+                    *conn = None;
+                }
+            }
+            let len = op.len();
+            self.handle(&mut conn, op);
+            self.shared.release(len);
+            if conn.is_none() {
+                connected_at = None;
+            } else if connected_at.is_none() {
+                connected_at = Some(Instant::now());
+            }
+            self.shared.pending.fetch_sub(1, Ordering::SeqCst);
         }
     }
-}
 
-fn handle_background_error<T, E: Into<Error>>(
-    handler: Option<fn(Error)>,
-    error: Result<T, E>,
+    fn connect(&self) -> Option<TcpConnection> {
+        self.report(TcpConnection::new(&self.target))
+    }
+
+    /// Count the outcome of a connection operation, passing the error, if
+    /// any, to the background error handler.
+    fn report<T, E: Into<Error>>(&self, result: Result<T, E>) -> Option<T> {
+        self.counters.report(result.is_ok());
+        self.shared.down.store(result.is_err(), Ordering::Relaxed);
+        self.handle_error(result)
+    }
+
+    /// Pass the error, if any, to the background error handler, or panic if
+    /// requested.
+    fn handle_error<T, E: Into<Error>>(&self, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) if self.target.panic_on_background_error => {
+                panic!("gelf_logger background thread failed: {:?}", err.into())
+            }
+            Err(err) => handle_background_error(self.target.background_error_handler, Err(err)),
+        }
+    }
+
+    fn handle(&self, conn: &mut Option<TcpConnection>, op: Op) {
+        if let Op::Batch(records) = op {
+            for data in records {
+                self.handle(conn, Op::Data(data));
+            }
+            return;
+        }
+        if let Op::Connect(tx) = op {
+            let result = match conn {
+                Some(_) => Ok(()),
+                None => TcpConnection::new(&self.target).map(|new| *conn = Some(new)),
+            };
+            self.counters.report(result.is_ok());
+            self.shared.down.store(result.is_err(), Ordering::Relaxed);
+            let _ = tx.send(result);
+            return;
+        }
+        if let (Op::Data(data), Some(max_age)) = (&op, self.target.max_record_age) {
+            if is_stale(data, max_age) {
+                self.counters.stale.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        if conn.is_none() {
+            *conn = self.connect();
+        }
+
+        if let Some(conn_ref) = conn {
+            if !self.replay(conn_ref) {
+                *conn = None;
+            }
+        }
+
+        let Some(conn_ref) = conn else {
+            if let Op::Data(data) = op {
+                self.spill(&data);
+            }
+            return;
+        };
+        match op {
+            Op::Data(mut data) => {
+                if self.target.measure_queue_latency {
+                    data = stamp(data, "_sent_at");
+                }
+                let Err(err) = conn_ref.write_all(&data) else {
+                    self.counters.report_sent();
+                    return;
+                };
+                let retry = self.target.on_write_timeout == WriteTimeoutPolicy::Retry
+                    && matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    );
+                self.report::<(), _>(Err(err));
+                *conn = None;
+                if retry {
+                    *conn = self.connect();
+                    if let Some(conn_ref) = conn {
+                        if self.report(conn_ref.write_all(&data)).is_some() {
+                            self.counters.report_sent();
+                            return;
+                        }
+                        *conn = None;
+                    }
+                }
+                self.spill(&data);
+            }
+            Op::Flush(tx) => {
+                if self.report(conn_ref.flush()).is_none() {
+                    *conn = None;
+                }
+                let _ = tx.send(());
+            }
+            Op::Connect(_) | Op::Batch(_) => {}
+        }
+    }
+
+    /// Store a record that couldn't be sent in the spill file, if any.
+    fn spill(&self, data: &[u8]) {
+        if let Some(spill) = &self.spill {
+            spill.push(data);
+        }
+    }
+
+    /// Send the records stored in the spill file, if any. Returns `false` if
+    /// the connection failed.
+    fn replay(&self, conn: &mut TcpConnection) -> bool {
+        let Some(spill) = self.spill.as_ref().filter(|spill| !spill.is_empty()) else {
+            return true;
+        };
+        let Some(records) = self.handle_error(spill.drain()) else {
+            return true;
+        };
+
+        let mut records = records.into_iter();
+        while let Some(data) = records.next() {
+            if self.report(conn.write_all(&data)).is_some() {
+                self.counters.report_sent();
+            } else {
+                spill.push(&data);
+                records.for_each(|data| {
+                    spill.push(&data);
+                });
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// How often the background threads try to reconnect while records are sent
+/// to the fallback target.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The name of the background threads.
+const WORKER_NAME: &str = "gelf-writer";
+
+/// The sending half of the channel between the caller and background threads.
+#[derive(Debug)]
+pub(crate) enum Sender {
+    /// The signal is notified each time an operation is received, freeing a
+    /// slot.
+    Bounded(mpsc::SyncSender<Op>, Arc<Signal>),
+    Unbounded(mpsc::Sender<Op>),
+}
+
+impl Sender {
+    fn channel(buffer_size: usize, unbounded: bool) -> (Self, Receiver) {
+        if unbounded {
+            let (tx, rx) = mpsc::channel();
+            (Self::Unbounded(tx), Receiver { rx, signal: None })
+        } else {
+            let (tx, rx) = mpsc::sync_channel(buffer_size);
+            let signal = Arc::new(Signal::default());
+            let rx = Receiver {
+                rx,
+                signal: Some(signal.clone()),
+            };
+            (Self::Bounded(tx, signal), rx)
+        }
+    }
+
+    /// Send the operation to the background thread. If a deadline is specified
+    /// and the channel is full, wait for a slot until it is reached. The
+    /// operation is given back if it couldn't be sent.
+    fn send_until(&self, op: Op, deadline: Option<Instant>) -> Result<(), Op> {
+        let (tx, signal) = match self {
+            Self::Bounded(tx, signal) => (tx, signal),
+            Self::Unbounded(tx) => return tx.send(op).map_err(|err| err.0),
+        };
+        if deadline.is_none() {
+            return tx.send(op).map_err(|err| err.0);
+        }
+        // Only given back if not sent.
+        let mut op = Some(op);
+        signal.wait_until(deadline, || {
+            let Some(next) = op.take() else {
+                return true;
+            };
+            match tx.try_send(next) {
+                Ok(()) => true,
+                Err(mpsc::TrySendError::Full(back)) => {
+                    op = Some(back);
+                    false
+                }
+                Err(mpsc::TrySendError::Disconnected(back)) => {
+                    op = Some(back);
+                    true
+                }
+            }
+        });
+        op.map_or(Ok(()), Err)
+    }
+}
+
+/// The receiving half of the channel between the caller and background
+/// threads, waking up the callers waiting for a slot.
+#[derive(Debug)]
+pub(crate) struct Receiver {
+    rx: mpsc::Receiver<Op>,
+    signal: Option<Arc<Signal>>,
+}
+
+impl Receiver {
+    pub(crate) fn recv(&self) -> Result<Op, mpsc::RecvError> {
+        let op = self.rx.recv()?;
+        self.received();
+        Ok(op)
+    }
+
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<Op, mpsc::RecvTimeoutError> {
+        let op = self.rx.recv_timeout(timeout)?;
+        self.received();
+        Ok(op)
+    }
+
+    #[cfg(test)]
+    fn try_iter(&self) -> impl Iterator<Item = Op> + '_ {
+        iter::from_fn(|| {
+            let op = self.rx.try_recv().ok()?;
+            self.received();
+            Some(op)
+        })
+    }
+
+    fn received(&self) {
+        if let Some(signal) = &self.signal {
+            signal.notify();
+        }
+    }
+}
+
+/// Wakes up the threads waiting for a background thread to make progress.
+#[derive(Debug, Default)]
+pub(crate) struct Signal {
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+impl Signal {
+    /// Call `done` until it returns `true`, waiting for a notification between
+    /// the calls, and until the deadline at most. Returns whether it is done.
+    fn wait_until(&self, deadline: Option<Instant>, mut done: impl FnMut() -> bool) -> bool {
+        let mut guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        while !done() {
+            guard = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return false;
+                    }
+                    self.cond
+                        .wait_timeout(guard, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .cond
+                    .wait(guard)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+        true
+    }
+
+    /// Wake up the waiting threads. The state they wait on must be updated
+    /// beforehand.
+    fn notify(&self) {
+        // Taking the lock guarantees that a thread which saw the previous
+        // state is already waiting.
+        drop(self.lock.lock().unwrap_or_else(PoisonError::into_inner));
+        self.cond.notify_all();
+    }
+}
+
+pub(crate) enum Op {
+    Data(Vec<u8>),
+    /// Several records queued at once, see [`GelfLogger::log_many`].
+    Batch(Vec<Vec<u8>>),
+    Flush(mpsc::SyncSender<()>),
+    Connect(mpsc::SyncSender<Result<(), Error>>),
+}
+
+impl Op {
+    /// The size in bytes of the record, if any.
+    fn len(&self) -> usize {
+        match self {
+            Op::Data(data) => data.len(),
+            Op::Batch(records) => records.iter().map(Vec::len).sum(),
+            Op::Flush(_) | Op::Connect(_) => 0,
+        }
+    }
+}
+
+/// The output target used by a [`GelfLogger`].
+#[derive(Clone, Debug)]
+// Only built once per logger, boxing `TcpTarget` wouldn't save anything.
+#[allow(clippy::large_enum_variant)]
+pub enum Target {
+    /// GELF records will be printed to stdout.
+    Stdout,
+    /// GELF records will be printed to stderr.
+    Stderr,
+    /// GELF records will be kept in memory, see [`Builder::memory`].
+    Memory(MemoryHandle),
+    /// GELF records will be forwarded over TCP.
+    Tcp(TcpTarget),
+    /// GELF records will be posted to an HTTP input.
+    #[cfg(feature = "http")]
+    Http(http::HttpTarget),
+}
+
+/// The behavior of log calls when the buffer between the caller and background
+/// threads is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullBufferPolicy {
+    /// Block the current thread until there is room for the record.
+    #[default]
+    Wait,
+    /// Drop the record.
+    Discard,
+    /// Block the current thread until there is room for the record, dropping
+    /// it if the timeout is reached first.
+    WaitTimeout(Duration),
+}
+
+impl FullBufferPolicy {
+    fn deadline(self) -> Option<Instant> {
+        match self {
+            FullBufferPolicy::Wait => None,
+            FullBufferPolicy::Discard => Some(Instant::now()),
+            FullBufferPolicy::WaitTimeout(timeout) => Some(Instant::now() + timeout),
+        }
+    }
+}
+
+/// How each record is delimited on a TCP stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Records end with a newline unless disabled with [`Builder::newline`],
+    /// followed by a null character if enabled with
+    /// [`Builder::null_character`].
+    #[default]
+    NewlineDelimited,
+    /// Records end with a null character.
+    NullDelimited,
+    /// Records are prefixed by their length in bytes and a space, as defined
+    /// by RFC 6587.
+    OctetCounted,
+}
+
+impl Framing {
+    pub(crate) fn frame(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Framing::NewlineDelimited => data,
+            Framing::NullDelimited => {
+                let mut framed = trim_delimiters(&data).to_vec();
+                framed.push(b'\0');
+                framed
+            }
+            Framing::OctetCounted => {
+                let payload = trim_delimiters(&data);
+                let mut framed = format!("{} ", payload.len()).into_bytes();
+                framed.extend_from_slice(payload);
+                framed
+            }
+        }
+    }
+}
+
+/// The behavior of the background thread when writing a record times out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteTimeoutPolicy {
+    /// Drop the record and reconnect on the next one.
+    #[default]
+    Drop,
+    /// Reconnect and write the record again, once. As part of the record may
+    /// have been received before the timeout, the remote host can get it twice
+    /// or get a truncated copy.
+    Retry,
+}
+
+/// A TCP target used to send the GELF records.
+#[derive(Clone, Debug)]
+pub struct TcpTarget {
+    /// The hostname used to resolve the remote host and establish the TLS
+    /// handshake if requested.
+    pub hostname: String,
+    /// The remote port to connect to.
+    pub port: u16,
+    /// Whether to use TLS over TCP. The hostname specified above will be used
+    /// to perform the TLS handshake.
+    pub tls: bool,
+    /// Set the connection timeout duration. If `None` is specified, the socket
+    /// connection phase can block indefinitely.
+    pub connect_timeout: Option<Duration>,
+    /// Connect when the logger is built, retrying up to the given number of
+    /// times with the given backoff in between, and fail if every attempt
+    /// does. If `None` is specified, the background thread connects lazily.
+    pub connect_retries: Option<(u32, Duration)>,
+    /// Set the connection write timeout duration. If `None` is specified, the
+    /// socket write calls can block indefinitely.
+    pub write_timeout: Option<Duration>,
+    /// Set the connection read timeout duration, only used by the TLS
+    /// handshake. If `None` is specified, a stalled handshake can block
+    /// indefinitely.
+    pub read_timeout: Option<Duration>,
+    /// Set the number of messages that can be queued between the caller and
+    /// background threads. If too many log calls are made and the background is
+    /// too slow, this buffer will fill up. When full, the full buffer policy
+    /// applies, blocking calls on the current thread by default.
+    pub buffer_size: usize,
+    /// Bound the queue between the caller and background threads by the total
+    /// size in bytes of the queued records instead, ignoring `buffer_size`.
+    /// When the budget is exceeded, the full buffer policy applies. A record
+    /// larger than the budget is still accepted when the queue is empty.
+    pub buffer_bytes: Option<usize>,
+    /// Use an unbounded channel between the caller and background threads,
+    /// ignoring `buffer_size`. Log calls never block, but if the background
+    /// thread can't keep up, queued records will grow in memory without limit
+    /// and may eventually exhaust it.
+    pub unbounded: bool,
+    /// Set the behavior of log calls when the buffer is full.
+    pub full_buffer_policy: FullBufferPolicy,
+    /// Write records directly from the calling thread when the connection is
+    /// established and the background thread is idle, saving a thread hop.
+    /// Records are never reordered relative to queued ones.
+    pub inline_when_idle: bool,
+    /// Set how each record is delimited on the stream.
+    pub framing: Framing,
+    /// Set the number of background threads, each with its own connection
+    /// and buffer of `buffer_size` records. Records are dispatched to them in
+    /// turn, so their order is only kept with a single thread.
+    pub worker_count: usize,
+    /// Set the stack size of the background threads. If `None` is specified,
+    /// the default of the standard library applies.
+    pub worker_stack_size: Option<usize>,
+    /// Drop the records whose GELF timestamp is older than this once the
+    /// background thread dequeues them, e.g. after an outage, counting them in
+    /// [`GelfLogger::stats`]. If `None` is specified, records are sent
+    /// whatever their age.
+    pub max_record_age: Option<Duration>,
+    /// Stamp the records with `_queued_at` when they are logged and `_sent_at`
+    /// when the background thread writes them, in seconds since UNIX epoch,
+    /// so that the queue delay can be computed downstream. `_sent_at` is
+    /// appended to the serialized record, after the sorted fields, if any.
+    pub measure_queue_latency: bool,
+    /// Buffer the records written by the background threads, writing them to
+    /// the connection at once when they would exceed this many bytes or on
+    /// flush. Records still buffered when the connection fails are lost. If
+    /// `None` is specified, each record is written as soon as dequeued.
+    pub flush_high_water: Option<usize>,
+    /// Set the behavior when writing a record exceeds the `write_timeout`.
+    pub on_write_timeout: WriteTimeoutPolicy,
+    /// Close and reestablish the connection once it has been open for this
+    /// duration, between two records. If `None` is specified, the connection
+    /// is kept until it fails.
+    pub max_connection_age: Option<Duration>,
+    /// Store the records that couldn't be queued because the buffer is full,
+    /// or sent because the remote host is unreachable, into a file. They are
+    /// replayed once the connection is established, including after a restart.
+    pub spill_file: Option<SpillFile>,
+    /// Register a static function that will be called when errors occur in the
+    /// background thread.
+    pub background_error_handler: Option<fn(Error)>,
+    /// Panic in the background thread when an error occurs there instead of
+    /// calling the background error handler, to surface misconfigurations
+    /// during development. Records are no longer written afterwards.
+    pub panic_on_background_error: bool,
+    /// Establish the connections with this function instead of connecting to
+    /// `hostname` and `port`, e.g. through a tunnel managed by the caller. The
+    /// TLS and timeout options don't apply to the streams it returns.
+    pub connection_factory: Option<ConnectionFactory>,
+    /// Register a static function that will be called on the socket once
+    /// created, before binding it to `bind_addr` and connecting it, to set
+    /// options not modeled by this target, e.g. `SO_REUSEADDR` or the DSCP
+    /// marking.
+    pub configure_socket: Option<fn(&Socket) -> io::Result<()>>,
+    /// Bind the socket to this local address before connecting, so that the
+    /// records leave from a given interface. If `None` is specified, the
+    /// operating system picks it.
+    pub bind_addr: Option<SocketAddr>,
+}
+
+/// A function establishing a connection to the remote host, called by the
+/// background thread whenever it needs to (re)connect.
+pub type ConnectionFactory = fn() -> io::Result<Box<dyn Write + Send>>;
+
+impl Default for TcpTarget {
+    /// Crate TCP target with the following placeholders:
+    /// ```rust,ignore
+    /// TcpTarget {
+    ///     hostname: "127.0.0.1".to_owned(),
+    ///     port: 2202,
+    ///     tls: false,
+    ///     connect_timeout: None,
+    ///     connect_retries: None,
+    ///     write_timeout: None,
+    ///     read_timeout: None,
+    ///     buffer_size: 1_000,
+    ///     buffer_bytes: None,
+    ///     unbounded: false,
+    ///     full_buffer_policy: FullBufferPolicy::Wait,
+    ///     inline_when_idle: false,
+    ///     framing: Framing::NewlineDelimited,
+    ///     worker_count: 1,
+    ///     worker_stack_size: None,
+    ///     max_record_age: None,
+    ///     measure_queue_latency: false,
+    ///     flush_high_water: None,
+    ///     on_write_timeout: WriteTimeoutPolicy::Drop,
+    ///     max_connection_age: None,
+    ///     spill_file: None,
+    ///     background_error_handler: None,
+    ///     panic_on_background_error: false,
+    ///     connection_factory: None,
+    ///     configure_socket: None,
+    ///     bind_addr: None,
+    /// }
+    /// ```
+    fn default() -> Self {
+        Self {
+            hostname: "127.0.0.1".to_owned(),
+            port: 2202,
+            tls: false,
+            connect_timeout: None,
+            connect_retries: None,
+            write_timeout: None,
+            read_timeout: None,
+            buffer_size: 1_000,
+            buffer_bytes: None,
+            unbounded: false,
+            full_buffer_policy: FullBufferPolicy::Wait,
+            inline_when_idle: false,
+            framing: Framing::NewlineDelimited,
+            worker_count: 1,
+            worker_stack_size: None,
+            max_record_age: None,
+            measure_queue_latency: false,
+            flush_high_water: None,
+            on_write_timeout: WriteTimeoutPolicy::Drop,
+            max_connection_age: None,
+            spill_file: None,
+            background_error_handler: None,
+            panic_on_background_error: false,
+            connection_factory: None,
+            configure_socket: None,
+            bind_addr: None,
+        }
+    }
+}
+
+enum TcpConnection {
+    Raw(TcpStream),
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    Tls(Box<tls::TlsStream>),
+    Custom(Box<dyn Write + Send>),
+    Buffered(Box<BufWriter<TcpConnection>>),
+}
+
+impl fmt::Debug for TcpConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpConnection::Raw(stream) => f.debug_tuple("Raw").field(stream).finish(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            TcpConnection::Tls(stream) => f.debug_tuple("Tls").field(stream).finish(),
+            TcpConnection::Custom(_) => f.write_str("Custom"),
+            TcpConnection::Buffered(writer) => f.debug_tuple("Buffered").field(writer).finish(),
+        }
+    }
+}
+
+impl TcpConnection {
+    fn new(target: &TcpTarget) -> Result<Self, Error> {
+        let conn = Self::unbuffered(target)?;
+        Ok(match target.flush_high_water {
+            Some(capacity) => Self::Buffered(Box::new(BufWriter::with_capacity(capacity, conn))),
+            None => conn,
+        })
+    }
+
+    fn unbuffered(target: &TcpTarget) -> Result<Self, Error> {
+        if let Some(factory) = target.connection_factory {
+            return Ok(Self::Custom(factory()?));
+        }
+        let socket_addr = (target.hostname.as_str(), target.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "hostname not resolved"))?;
+        let socket = Socket::new(
+            Domain::for_address(socket_addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        if let Some(configure) = target.configure_socket {
+            configure(&socket)?;
+        }
+        if let Some(bind_addr) = target.bind_addr {
+            socket.bind(&bind_addr.into())?;
+        }
+        match target.connect_timeout {
+            Some(timeout) => socket.connect_timeout(&socket_addr.into(), timeout),
+            None => socket.connect(&socket_addr.into()),
+        }?;
+        let stream = TcpStream::from(socket);
+        stream.set_write_timeout(target.write_timeout)?;
+        stream.set_read_timeout(target.read_timeout)?;
+
+        if !target.tls {
+            return Ok(Self::Raw(stream));
+        }
+
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        return Ok(Self::Tls(Box::new(tls::connect(&target.hostname, stream)?)));
+        #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+        return Err(Error::TlsUnavailable);
+    }
+
+    /// Connect to the target, retrying on failure.
+    fn with_retries(target: &TcpTarget, retries: u32, backoff: Duration) -> Result<Self, Error> {
+        let mut attempt = 0;
+        loop {
+            match Self::new(target) {
+                Err(_) if attempt < retries => {
+                    attempt += 1;
+                    thread::sleep(backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for TcpConnection {
+    fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> {
+        match self {
+            TcpConnection::Raw(stream) => stream.write(data),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            TcpConnection::Tls(stream) => stream.write(data),
+            TcpConnection::Custom(stream) => stream.write(data),
+            TcpConnection::Buffered(writer) => writer.write(data),
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        match self {
+            TcpConnection::Raw(stream) => stream.write_all(data),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            TcpConnection::Tls(stream) => stream.write_all(data),
+            TcpConnection::Custom(stream) => stream.write_all(data),
+            TcpConnection::Buffered(writer) => writer.write_all(data),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        match self {
+            TcpConnection::Raw(stream) => stream.flush(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            TcpConnection::Tls(stream) => stream.flush(),
+            TcpConnection::Custom(stream) => stream.flush(),
+            TcpConnection::Buffered(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Send the records to the TCP target from the current thread, connecting,
+/// writing, flushing and closing the connection before returning. This suits
+/// short scripts that don't need a global logger nor a background thread.
+///
+/// Records are sent as is, each followed by a newline and delimited as set by
+/// `target.framing`. The buffering options of the target are ignored.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use gelf_logger::{send_blocking, GelfRecord, TcpTarget};
+/// use log::Record;
+///
+/// let record = Record::builder().args(format_args!("done")).build();
+/// send_blocking(&TcpTarget::default(), &[GelfRecord::from(&record)]).unwrap();
+/// ```
+pub fn send_blocking(target: &TcpTarget, records: &[GelfRecord<'_>]) -> Result<(), Error> {
+    let mut conn = TcpConnection::new(target)?;
+    for record in records {
+        let mut data = record.to_json()?;
+        data.push(b'\n');
+        conn.write_all(&target.framing.frame(data))?;
+    }
+    Ok(conn.flush()?)
+}
+
+/// Whether the GELF `timestamp` of the serialized record is older than
+/// `max_age`. Records without one are never stale.
+fn is_stale(data: &[u8], max_age: Duration) -> bool {
+    #[derive(serde::Deserialize)]
+    struct Stamp {
+        timestamp: Option<f64>,
+    }
+
+    // Skip the octet counting prefix, if any.
+    let start = data.iter().position(|&byte| byte == b'{').unwrap_or(0);
+    let Ok(Stamp {
+        timestamp: Some(timestamp),
+    }) = serde_json::from_slice(trim_delimiters(&data[start..]))
+    else {
+        return false;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    now - timestamp > max_age.as_secs_f64()
+}
+
+/// Add the `_queued_at` field, holding the current time, to the record.
+fn stamp_queued_at(record: &mut GelfRecord<'_>) {
+    record
+        .additional_fields
+        .insert("_queued_at".to_owned(), Value::from(record::now()));
+}
+
+/// Add a field holding the current time to a serialized record, updating its
+/// octet counting prefix, if any.
+fn stamp(data: Vec<u8>, name: &str) -> Vec<u8> {
+    let (Some(start), Some(end)) = (
+        data.iter().position(|&byte| byte == b'{'),
+        data.iter().rposition(|&byte| byte == b'}'),
+    ) else {
+        return data;
+    };
+    let field = format!(",\"{name}\":{}", Value::from(record::now()));
+    let mut stamped = Vec::with_capacity(data.len() + field.len() + 4);
+    if start > 0 {
+        let len = end - start + 1 + field.len();
+        stamped.extend_from_slice(format!("{len} ").as_bytes());
+    }
+    stamped.extend_from_slice(&data[start..end]);
+    stamped.extend_from_slice(field.as_bytes());
+    stamped.extend_from_slice(&data[end..]);
+    stamped
+}
+
+/// Remove the newline and null characters ending a serialized record.
+pub(crate) fn trim_delimiters(data: &[u8]) -> &[u8] {
+    let end = data
+        .iter()
+        .rposition(|byte| !matches!(byte, b'\n' | b'\0'))
+        .map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Whether `target` is `module` or one of its submodules.
+fn is_submodule(target: &str, module: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+pub(crate) fn handle_background_error<T, E: Into<Error>>(
+    handler: Option<fn(Error)>,
+    error: Result<T, E>,
 ) -> Option<T> {
     match (handler, error) {
         (Some(handler), Err(err)) => {
@@ -311,3 +1674,1244 @@ fn handle_background_error<T, E: Into<Error>>(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::{self, BufRead, BufReader, Write},
+        iter,
+        net::TcpListener,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use log::{Level, Log, Record};
+    use serde_json::Value;
+    use socket2::SockRef;
+
+    use super::TcpConnection;
+    use super::{
+        stamp, Framing, FullBufferPolicy, GelfLogger, Op, Pipe, Receiver, Sender, TcpTarget,
+        Worker, WriteTimeoutPolicy, Writer,
+    };
+    use crate::{Builder, Error, GelfRecord, MemoryHandle, Target};
+
+    /// Build a logger whose records are captured instead of being written.
+    fn capture(builder: Builder, f: impl FnOnce(&GelfLogger)) -> Vec<Value> {
+        let mut logger = builder.build().unwrap();
+        let (tx, rx) = mpsc::sync_channel(100);
+        logger.writer = Arc::new(Writer::Pipe(Pipe::new(
+            Sender::Bounded(tx, Arc::default()),
+            FullBufferPolicy::Wait,
+        )));
+        f(&logger);
+
+        let records = rx
+            .try_iter()
+            .flat_map(|op| match op {
+                Op::Data(data) => vec![data],
+                Op::Batch(records) => records,
+                Op::Flush(_) | Op::Connect(_) => Vec::new(),
+            })
+            .filter_map(|data| serde_json::from_slice(&data).ok())
+            .collect();
+        drop(rx);
+        records
+    }
+
+    /// Accept a single connection and return every record received until EOF.
+    fn listen() -> (u16, thread::JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            BufReader::new(stream)
+                .lines()
+                .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                .collect()
+        });
+        (port, server)
+    }
+
+    fn log(logger: &GelfLogger, level: Level, message: &str) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{message}"))
+                .level(level)
+                .build(),
+        );
+    }
+
+    #[test]
+    fn sequence() {
+        let records = capture(Builder::new().with_sequence(true), |logger| {
+            log(logger, Level::Error, "first");
+            log(logger, Level::Error, "second");
+        });
+        assert_eq!(records[0]["_seq"], 1);
+        assert_eq!(records[1]["_seq"], 2);
+
+        let records = capture(Builder::new(), |logger| {
+            log(logger, Level::Error, "first");
+        });
+        assert!(records[0].get("_seq").is_none());
+    }
+
+    #[test]
+    fn sort_fields() {
+        let kvs = [("beta", 2), ("delta", 4)];
+        let records = capture(
+            Builder::new()
+                .extend_additional_fields([
+                    ("gamma".to_owned(), Value::from(3)),
+                    ("alpha".to_owned(), Value::from(1)),
+                ])
+                .sort_fields(true),
+            |logger| {
+                logger.log(
+                    &Record::builder()
+                        .level(Level::Error)
+                        .key_values(&kvs)
+                        .build(),
+                );
+            },
+        );
+        // `_levelname` and `_facility` are not additional fields and always come
+        // first.
+        let keys: Vec<_> = records[0].as_object().unwrap().keys().skip(7).collect();
+        assert_eq!(keys, ["_alpha", "_beta_long", "_delta_long", "_gamma"]);
+    }
+
+    #[test]
+    fn target_raw_additional_fields() {
+        let builder = Builder::new()
+            .extend_raw_additional_fields([("X-OVH-TOKEN".to_owned(), Value::from("default"))])
+            .extend_raw_additional_fields_for_target(
+                "app",
+                [("X-OVH-TOKEN".to_owned(), Value::from("a"))],
+            )
+            .extend_raw_additional_fields_for_target(
+                "app::billing",
+                [("X-OVH-TOKEN".to_owned(), Value::from("b"))],
+            );
+        let records = capture(builder, |logger| {
+            for target in ["app", "app::billing::invoice", "application", "other"] {
+                logger.log(&Record::builder().level(Level::Error).target(target).build());
+            }
+        });
+        let tokens: Vec<_> = records.iter().map(|r| &r["X-OVH-TOKEN"]).collect();
+        assert_eq!(tokens, ["a", "b", "default", "default"]);
+    }
+
+    #[test]
+    fn memory() {
+        let (builder, memory) = Builder::new().memory();
+        let logger = builder.build().unwrap();
+        log(&logger, Level::Error, "first");
+        log(&logger, Level::Info, "filtered");
+        logger.flush();
+
+        let records = memory.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].short_message, "first");
+        assert!(memory.records().is_empty());
+    }
+
+    #[test]
+    fn strict() {
+        let (builder, memory) = Builder::new().strict(true).memory();
+        let logger = builder.build().unwrap();
+        log(&logger, Level::Error, "");
+        log(&logger, Level::Error, "valid");
+
+        let records = memory.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].short_message, "valid");
+        assert_eq!(logger.stats().invalid, 1);
+    }
+
+    #[test]
+    fn route() {
+        let memory_b = MemoryHandle::default();
+        let (builder, memory_a) = Builder::new()
+            .route("b", Target::Memory(memory_b.clone()))
+            .memory();
+        let logger = builder.build().unwrap();
+
+        log(&logger, Level::Error, "default");
+        let kvs = [("__gelf_target", "b")];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("routed"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+        let kvs = [("__gelf_target", "unknown")];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("unknown"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let records = memory_a.take();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].short_message, "default");
+        assert_eq!(records[1].short_message, "unknown");
+        let records = memory_b.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].short_message, "routed");
+        assert!(!records[0].contains("__gelf_target"));
+    }
+
+    #[test]
+    fn flush_on_drop() {
+        let flushed_on_drop = |enabled| {
+            let mut logger = Builder::new()
+                .flush_on_drop(enabled)
+                .drop_flush_timeout(Some(Duration::from_millis(10)))
+                .build()
+                .unwrap();
+            let (tx, rx) = mpsc::sync_channel(10);
+            logger.writer = Arc::new(Writer::Pipe(Pipe::new(
+                Sender::Bounded(tx, Arc::default()),
+                FullBufferPolicy::Wait,
+            )));
+            drop(logger);
+            let flushed = rx.try_iter().any(|op| matches!(op, Op::Flush(_)));
+            flushed
+        };
+        assert!(flushed_on_drop(true));
+        assert!(!flushed_on_drop(false));
+    }
+
+    #[test]
+    fn flush_marker() {
+        let mut logger = Builder::new().build().unwrap();
+        let (tx, rx) = mpsc::sync_channel(10);
+        logger.writer = Arc::new(Writer::Pipe(Pipe::new(
+            Sender::Bounded(tx, Arc::default()),
+            FullBufferPolicy::Wait,
+        )));
+        let (events_tx, events) = mpsc::channel();
+        thread::spawn(move || {
+            for op in rx {
+                match op {
+                    Op::Data(data) => {
+                        let record: Value = serde_json::from_slice(&data).unwrap();
+                        events_tx.send(Some(record)).unwrap();
+                    }
+                    Op::Flush(ack) => {
+                        events_tx.send(None).unwrap();
+                        let _ = ack.send(());
+                    }
+                    Op::Batch(_) | Op::Connect(_) => {}
+                }
+            }
+        });
+
+        log(&logger, Level::Error, "normal");
+        let kvs = [("__gelf_flush", true)];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("flushed"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        // The flush is acknowledged once both records have been dequeued.
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].as_ref().unwrap()["short_message"], "normal");
+        let flushed = events[1].as_ref().unwrap();
+        assert_eq!(flushed["short_message"], "flushed");
+        assert!(flushed.get("___gelf_flush_bool").is_none());
+        assert!(flushed.get("___gelf_flush").is_none());
+        assert!(events[2].is_none());
+    }
+
+    #[test]
+    fn redact_fields() {
+        #[derive(serde::Serialize)]
+        struct Login {
+            user: &'static str,
+            password: &'static str,
+        }
+
+        let (builder, memory) = Builder::new()
+            .redact_fields(&["password", "_login_password"])
+            .memory();
+        let logger = builder.build().unwrap();
+        let login = Login {
+            user: "foo",
+            password: "hunter2",
+        };
+        let kvs = [
+            ("login", log::kv::Value::from_serde(&login)),
+            ("password", log::kv::Value::from("hunter2")),
+        ];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("login"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let records = memory.take();
+        assert_eq!(records[0].get_str("password"), Some("***"));
+        assert_eq!(records[0].get_str("login_password"), Some("***"));
+        assert_eq!(records[0].get_str("login_user"), Some("foo"));
+    }
+
+    #[test]
+    fn redact_fields_type_suffix() {
+        let (builder, memory) = Builder::new()
+            .type_suffix(true)
+            .extend_additional_fields([("token".to_owned(), Value::from(1234))])
+            .redact_fields(&["password", "token", "admin"])
+            .memory();
+        let logger = builder.build().unwrap();
+        let kvs = [
+            ("password", log::kv::Value::from(1234)),
+            ("admin", log::kv::Value::from(true)),
+            ("count", log::kv::Value::from(3)),
+        ];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("login"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let fields = &memory.take()[0].additional_fields;
+        for name in ["_password", "_token", "_admin"] {
+            assert_eq!(fields[name], "***");
+        }
+        for name in ["_password_long", "_token_long", "_admin_bool"] {
+            assert!(!fields.contains_key(name));
+        }
+        assert_eq!(fields["_count_long"], 3);
+    }
+
+    #[test]
+    fn framing() {
+        let data = b"{\"a\":1}\n\0".to_vec();
+        assert_eq!(Framing::NewlineDelimited.frame(data.clone()), data);
+        assert_eq!(Framing::NullDelimited.frame(data.clone()), b"{\"a\":1}\0");
+        assert_eq!(Framing::OctetCounted.frame(data), b"7 {\"a\":1}");
+    }
+
+    #[test]
+    fn newline() {
+        for (newline, null_character, suffix) in [
+            (true, false, &b"}\n"[..]),
+            (true, true, b"}\n\0"),
+            (false, true, b"}\0"),
+            (false, false, b"}"),
+        ] {
+            let mut logger = Builder::new()
+                .newline(newline)
+                .null_character(null_character)
+                .build()
+                .unwrap();
+            let (tx, rx) = mpsc::sync_channel(1);
+            logger.writer = Arc::new(Writer::Pipe(Pipe::new(
+                Sender::Bounded(tx, Arc::default()),
+                FullBufferPolicy::Wait,
+            )));
+            log(&logger, Level::Error, "message");
+            let Ok(Op::Data(data)) = rx.try_recv() else {
+                panic!("no record");
+            };
+            assert!(data.ends_with(suffix), "{newline} {null_character}");
+            assert!(!data[..data.len() - suffix.len()].ends_with(b"\n"));
+        }
+    }
+
+    #[test]
+    fn keep_last() {
+        let records = capture(Builder::new().keep_last(2), |logger| {
+            for message in ["first", "second", "third"] {
+                log(logger, Level::Error, message);
+            }
+            let recent = logger.recent();
+            assert_eq!(recent.len(), 2);
+            assert_eq!(recent[0].short_message, "second");
+            assert_eq!(recent[1].short_message, "third");
+        });
+        assert_eq!(records.len(), 3);
+    }
+
+    #[test]
+    fn clone() {
+        let records = capture(Builder::new(), |logger| {
+            let other = logger.clone();
+            log(logger, Level::Error, "first");
+            log(&other, Level::Error, "second");
+            other.log_record(&GelfRecord::from(
+                &Record::builder().args(format_args!("third")).build(),
+            ));
+        });
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1]["short_message"], "second");
+        assert_eq!(records[2]["short_message"], "third");
+    }
+
+    #[test]
+    fn unbounded() {
+        let (tx, rx) = Sender::channel(1, true);
+        let writer = Writer::Pipe(Pipe::new(tx, FullBufferPolicy::Wait));
+        let start = Instant::now();
+        for _ in 0..10_000 {
+            assert!(writer.write_until(Op::Data(b"{}\n".to_vec()), None));
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(rx.try_iter().count(), 10_000);
+
+        let (tx, _rx) = Sender::channel(1, false);
+        let writer = Writer::Pipe(Pipe::new(tx, FullBufferPolicy::Wait));
+        let deadline = Some(Instant::now() + Duration::from_millis(10));
+        assert!(writer.write_until(Op::Data(b"{}\n".to_vec()), deadline));
+        assert!(!writer.write_until(Op::Data(b"{}\n".to_vec()), deadline));
+    }
+
+    #[test]
+    fn full_buffer_policy() {
+        let saturated = |policy| {
+            let (tx, rx) = Sender::channel(1, false);
+            let writer = Writer::Pipe(Pipe::new(tx, policy));
+            assert!(writer.write(Op::Data(b"{}\n".to_vec())));
+            (writer, rx)
+        };
+
+        let (writer, _rx) = saturated(FullBufferPolicy::Discard);
+        let start = Instant::now();
+        assert!(!writer.write(Op::Data(b"{}\n".to_vec())));
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let (writer, _rx) = saturated(FullBufferPolicy::WaitTimeout(Duration::from_millis(100)));
+        let start = Instant::now();
+        assert!(!writer.write(Op::Data(b"{}\n".to_vec())));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // The caller is woken up as soon as a slot is freed.
+        let (writer, rx) = saturated(FullBufferPolicy::WaitTimeout(Duration::from_secs(10)));
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            iter::from_fn(|| rx.recv().ok()).count()
+        });
+        let start = Instant::now();
+        assert!(writer.write(Op::Data(b"{}\n".to_vec())));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(writer);
+        assert_eq!(consumer.join().unwrap(), 2);
+
+        let (writer, rx) = saturated(FullBufferPolicy::Wait);
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            iter::from_fn(|| rx.recv().ok()).count()
+        });
+        let start = Instant::now();
+        assert!(writer.write(Op::Data(b"{}\n".to_vec())));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        drop(writer);
+        assert_eq!(consumer.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn report_dropped_records() {
+        let mut logger = Builder::new()
+            .report_dropped_records(Some(Duration::from_secs(60)))
+            .build()
+            .unwrap();
+        let (tx, rx) = Sender::channel(2, false);
+        logger.writer = Arc::new(Writer::Pipe(Pipe::new(tx, FullBufferPolicy::Discard)));
+        for message in ["first", "second", "third", "fourth"] {
+            log(&logger, Level::Error, message);
+        }
+        assert_eq!(logger.stats().dropped, 2);
+
+        let drain = |rx: &Receiver| {
+            rx.try_iter()
+                .filter_map(|op| match op {
+                    Op::Data(data) => serde_json::from_slice::<Value>(&data).ok(),
+                    Op::Batch(_) | Op::Flush(_) | Op::Connect(_) => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(drain(&rx).len(), 2);
+        log(&logger, Level::Error, "fifth");
+        let records = drain(&rx);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["short_message"], "2 records dropped");
+        assert_eq!(records[0]["level"], 4);
+        assert_eq!(records[0]["_dropped_long"], 2);
+        assert_eq!(records[1]["short_message"], "fifth");
+
+        // The next summary is throttled.
+        for message in ["sixth", "seventh", "eighth"] {
+            log(&logger, Level::Error, message);
+        }
+        drain(&rx);
+        log(&logger, Level::Error, "ninth");
+        let records = drain(&rx);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["short_message"], "ninth");
+        assert_eq!(logger.stats().dropped, 3);
+    }
+
+    #[test]
+    fn buffer_bytes() {
+        let (tx, rx) = Sender::channel(0, true);
+        let mut pipe = Pipe::new(tx, FullBufferPolicy::Discard);
+        pipe.lanes[0].budget = Some(10);
+        let writer = Writer::Pipe(pipe);
+        for _ in 0..3 {
+            assert!(writer.write(Op::Data(b"{}\n".to_vec())));
+        }
+        assert!(!writer.write(Op::Data(b"{}\n".to_vec())));
+
+        let Writer::Pipe(pipe) = &writer else {
+            unreachable!();
+        };
+        assert_eq!(pipe.lanes[0].shared.queued_bytes.load(Ordering::SeqCst), 9);
+
+        // A waiting caller is woken up as soon as bytes are released.
+        let shared = pipe.lanes[0].shared.clone();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            shared.release(3);
+        });
+        let start = Instant::now();
+        let deadline = Some(start + Duration::from_secs(10));
+        assert!(writer.write_until(Op::Data(b"{}\n".to_vec()), deadline));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        releaser.join().unwrap();
+        drop(rx);
+    }
+
+    #[test]
+    fn inline_when_idle() {
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .inline_when_idle(true)
+            .build()
+            .unwrap();
+        for i in 0..200 {
+            log(&logger, Level::Error, &i.to_string());
+            if i == 0 {
+                logger.flush();
+            }
+        }
+        drop(logger);
+
+        let records = server.join().unwrap();
+        let messages = records
+            .iter()
+            .map(|record| record["short_message"].as_str().unwrap().parse().unwrap())
+            .collect::<Vec<u32>>();
+        assert_eq!(messages, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn flush_timeout() {
+        // The connection is established but the record is never read, so the
+        // background thread blocks once the socket buffers are full.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        SockRef::from(&listener).set_recv_buffer_size(4096).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let logger = Builder::new()
+            .port(port)
+            .drop_flush_timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, &"a".repeat(8 << 20));
+
+        let start = Instant::now();
+        assert!(!logger.flush_timeout(Duration::from_millis(500)));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+        drop(listener);
+    }
+
+    #[test]
+    fn drop_flush() {
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .drop_flush_timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap();
+        for message in ["first", "second", "third"] {
+            log(&logger, Level::Error, message);
+        }
+        drop(logger);
+
+        let records = server.join().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2]["short_message"], "third");
+    }
+
+    #[test]
+    fn worker_count() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    thread::spawn(move || BufReader::new(stream).lines().count())
+                })
+                .collect();
+            readers
+                .into_iter()
+                .map(|reader| reader.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let logger = Builder::new().port(port).worker_count(4).build().unwrap();
+        for i in 0..100 {
+            log(&logger, Level::Error, &i.to_string());
+        }
+        logger.flush();
+        let Writer::Pipe(pipe) = &*logger.writer else {
+            unreachable!();
+        };
+        for lane in &pipe.lanes {
+            assert_eq!(lane.shared.pending.load(Ordering::SeqCst), 0);
+        }
+        drop(logger);
+
+        assert_eq!(server.join().unwrap(), [25; 4]);
+    }
+
+    #[test]
+    fn connect_retries() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(Builder::new()
+            .port(port)
+            .connect_retries(1, Duration::from_millis(10))
+            .build()
+            .is_err());
+
+        // Attempts are made after 0, 200 and 400ms.
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            BufReader::new(stream).lines().count()
+        });
+        let start = Instant::now();
+        let logger = Builder::new()
+            .port(port)
+            .connect_retries(2, Duration::from_millis(200))
+            .build()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        log(&logger, Level::Error, "connected");
+        drop(logger);
+        assert_eq!(server.join().unwrap(), 1);
+
+        assert!(Builder::new()
+            .hostname("unresolvable.invalid".to_owned())
+            .connect_retries(1, Duration::from_millis(10))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn send_blocking() {
+        let (port, server) = listen();
+        let target = TcpTarget {
+            port,
+            ..Default::default()
+        };
+        let records = ["first", "second"].map(|message| {
+            GelfRecord::from(&Record::builder().args(format_args!("{message}")).build())
+                .into_owned()
+        });
+        super::send_blocking(&target, &records).unwrap();
+
+        let received = server.join().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["short_message"], "first");
+        assert_eq!(received[1]["short_message"], "second");
+
+        let target = TcpTarget {
+            hostname: "unresolvable.invalid".to_owned(),
+            ..target
+        };
+        assert!(super::send_blocking(&target, &records).is_err());
+    }
+
+    #[test]
+    fn max_record_age() {
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .max_record_age(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let mut record = GelfRecord::from(&Record::builder().args(format_args!("stale")).build());
+        record.timestamp = record.timestamp.map(|timestamp| timestamp - 120.0);
+        logger.log_record(&record);
+        log(&logger, Level::Error, "fresh");
+        logger.flush();
+        assert_eq!(logger.stats().stale, 1);
+        assert_eq!(logger.stats().sent, 1);
+        drop(logger);
+
+        let records = server.join().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["short_message"], "fresh");
+    }
+
+    #[test]
+    fn measure_queue_latency() {
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .measure_queue_latency(true)
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "measured");
+        drop(logger);
+
+        let records = server.join().unwrap();
+        let queued_at = records[0]["_queued_at"].as_f64().unwrap();
+        let sent_at = records[0]["_sent_at"].as_f64().unwrap();
+        assert!(sent_at >= queued_at);
+
+        let data = Framing::OctetCounted.frame(b"{\"a\":1}\n".to_vec());
+        let stamped = String::from_utf8(stamp(data, "_b")).unwrap();
+        let (len, payload) = stamped.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), payload.len());
+        assert!(payload.starts_with("{\"a\":1,\"_b\":"));
+    }
+
+    #[test]
+    fn measure_queue_latency_sorted() {
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .measure_queue_latency(true)
+            .sort_fields(true)
+            .strict(true)
+            .build()
+            .unwrap();
+        let kvs = [("zeta", 1), ("alpha", 2)];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("measured"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+        drop(logger);
+
+        let records = server.join().unwrap();
+        let keys: Vec<_> = records[0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .filter(|key| {
+                ["_alpha_long", "_queued_at", "_sent_at", "_zeta_long"].contains(&key.as_str())
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            ["_alpha_long", "_queued_at", "_zeta_long", "_sent_at"]
+        );
+    }
+
+    #[test]
+    fn configure_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = TcpTarget {
+            port: listener.local_addr().unwrap().port(),
+            configure_socket: Some(|socket| socket.set_reuse_address(true)),
+            ..TcpTarget::default()
+        };
+        let TcpConnection::Raw(stream) = TcpConnection::new(&target).unwrap() else {
+            panic!("not a raw connection");
+        };
+        assert!(SockRef::from(&stream).reuse_address().unwrap());
+    }
+
+    #[test]
+    fn bind_addr() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bind_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let logger = Builder::new()
+            .port(listener.local_addr().unwrap().port())
+            .bind_addr(Some(bind_addr))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "bound");
+
+        let (_, peer_addr) = listener.accept().unwrap();
+        assert_eq!(peer_addr, bind_addr);
+    }
+
+    #[test]
+    fn fallback() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let memory = MemoryHandle::default();
+        let logger = Builder::new()
+            .port(port)
+            .fallback(Target::Memory(memory.clone()))
+            .build()
+            .unwrap();
+
+        // The first record reveals the outage.
+        log(&logger, Level::Error, "lost");
+        logger.flush();
+        log(&logger, Level::Error, "fallback");
+        let records = memory.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].short_message, "fallback");
+
+        // The background thread reconnects on its own.
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let start = Instant::now();
+        while logger.writer.is_down() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        log(&logger, Level::Error, "primary");
+        drop(logger);
+        let (stream, _) = listener.accept().unwrap();
+        let line = BufReader::new(stream).lines().next().unwrap().unwrap();
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["short_message"], "primary");
+        assert!(memory.take().is_empty());
+    }
+
+    #[test]
+    fn connection_factory() {
+        static SENT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+        /// An in-memory stream appending to `SENT`.
+        struct Pipe;
+
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                SENT.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logger = Builder::new()
+            .connection_factory(Some(|| Ok(Box::new(Pipe))))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "tunneled");
+        logger.flush();
+
+        let sent = SENT.lock().unwrap();
+        let record: Value = serde_json::from_slice(&sent).unwrap();
+        assert_eq!(record["short_message"], "tunneled");
+    }
+
+    #[test]
+    fn log_many() {
+        let (port, server) = listen();
+        let logger = Builder::new().port(port).build().unwrap();
+        let records = ["first", "second", "third"].map(|message| {
+            GelfRecord::from(
+                &Record::builder()
+                    .args(format_args!("{message}"))
+                    .level(Level::Error)
+                    .build(),
+            )
+            .into_owned()
+        });
+        logger.log_many(records);
+        drop(logger);
+
+        let records = server.join().unwrap();
+        let messages: Vec<_> = records.iter().map(|r| &r["short_message"]).collect();
+        assert_eq!(messages, ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn flush_high_water() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let record: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                tx.send(record["short_message"].as_str().unwrap().to_owned())
+                    .unwrap();
+            }
+        });
+
+        let logger = Builder::new()
+            .port(port)
+            .flush_high_water(Some(1024))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "first");
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // Each record is a few hundred bytes, exceeding the high-water mark
+        // well before the last one.
+        for _ in 0..10 {
+            log(&logger, Level::Error, "more");
+        }
+        let first = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first, "first");
+        assert!(rx.try_iter().count() < 10);
+
+        logger.flush();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "more");
+    }
+
+    #[test]
+    fn panic_on_background_error() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let worker = |panic_on_background_error| Worker {
+            target: TcpTarget {
+                port,
+                panic_on_background_error,
+                ..TcpTarget::default()
+            },
+            shared: Arc::default(),
+            spill: None,
+            counters: Arc::default(),
+        };
+        assert!(worker(false).connect().is_none());
+
+        let worker = worker(true);
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| worker.connect())).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("gelf_logger background thread failed"));
+        assert!(message.contains("ConnectionRefused"));
+        assert_eq!(worker.counters.errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn connect() {
+        let (port, server) = listen();
+        let logger = Builder::new().port(port).build().unwrap();
+        logger.connect().unwrap();
+        assert!(logger.connect().is_ok());
+        drop(logger);
+        assert!(server.join().unwrap().is_empty());
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let logger = Builder::new().port(port).build().unwrap();
+        let err = logger.connect().unwrap_err();
+        assert_eq!(err.io_kind(), Some(io::ErrorKind::ConnectionRefused));
+        assert_eq!(logger.stats().errors, 1);
+
+        // The background thread is still alive after a resolution failure.
+        let logger = Builder::new()
+            .hostname("unresolvable.invalid".to_owned())
+            .build()
+            .unwrap();
+        assert!(logger.connect().is_err());
+        assert!(logger.connect().is_err());
+        assert_eq!(logger.stats().errors, 2);
+    }
+
+    #[test]
+    fn serialization_error() {
+        static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+        let (port, server) = listen();
+        let logger = Builder::new()
+            .port(port)
+            .background_error_handler(Some(|err| {
+                if matches!(err, Error::Serialization(_)) {
+                    ERRORS.fetch_add(1, Ordering::Relaxed);
+                }
+            }))
+            .build()
+            .unwrap();
+        // JSON objects can't have tuple keys.
+        let tuple_keys = BTreeMap::from([((1, 2), "value")]);
+        let kvs = [
+            ("broken", log::kv::Value::from_serde(&tuple_keys)),
+            ("kept", log::kv::Value::from(42)),
+        ];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("partial"))
+                .level(Level::Error)
+                .key_values(&kvs)
+                .build(),
+        );
+        logger.flush();
+        assert_eq!(logger.stats().serialization_errors, 1);
+        assert_eq!(ERRORS.load(Ordering::Relaxed), 1);
+        drop(logger);
+
+        let records = server.join().unwrap();
+        assert_eq!(records[0]["short_message"], "partial");
+        assert_eq!(records[0]["_kept_long"], 42);
+        assert!(records[0].get("_broken").is_none());
+    }
+
+    #[test]
+    fn worker_name() {
+        static NAME: Mutex<Option<String>> = Mutex::new(None);
+
+        // Nothing listens on the port, so the handler is called by the
+        // background thread when connecting.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let logger = Builder::new()
+            .port(port)
+            .worker_stack_size(256 * 1024)
+            .background_error_handler(Some(|_| {
+                *NAME.lock().unwrap() = thread::current().name().map(str::to_owned);
+            }))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "unreachable");
+        logger.flush();
+
+        assert_eq!(NAME.lock().unwrap().as_deref(), Some("gelf-writer"));
+    }
+
+    #[test]
+    fn max_connection_age() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            (0..2)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    BufReader::new(stream).lines().count()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let logger = Builder::new()
+            .port(port)
+            .max_connection_age(Some(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "first");
+        logger.flush();
+        thread::sleep(Duration::from_millis(100));
+        log(&logger, Level::Error, "second");
+        drop(logger);
+
+        assert_eq!(server.join().unwrap(), [1, 1]);
+    }
+
+    #[test]
+    fn on_write_timeout() {
+        // Count the connections made while writing a record larger than the
+        // socket buffers to a listener that never reads.
+        fn connections(policy: WriteTimeoutPolicy) -> usize {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let logger = Builder::new()
+                .port(port)
+                .write_timeout(Some(Duration::from_millis(50)))
+                .on_write_timeout(policy)
+                .build()
+                .unwrap();
+            log(&logger, Level::Error, &"a".repeat(16 << 20));
+            logger.flush();
+
+            listener.set_nonblocking(true).unwrap();
+            let streams: Vec<_> = listener.incoming().map_while(Result::ok).collect();
+            streams.len()
+        }
+
+        // The flush reconnects in both cases, retrying adds one connection.
+        assert_eq!(
+            connections(WriteTimeoutPolicy::Retry),
+            connections(WriteTimeoutPolicy::Drop) + 1
+        );
+    }
+
+    #[test]
+    fn spill_to_disk() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let path = std::env::temp_dir().join(format!("gelf_logger_spill_{port}"));
+        let _ = std::fs::remove_file(&path);
+
+        let logger = Builder::new()
+            .port(port)
+            .spill_to_disk(path.clone(), 1 << 20)
+            .drop_flush_timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap();
+        for message in ["first", "second", "third"] {
+            log(&logger, Level::Error, message);
+        }
+        logger.flush();
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            BufReader::new(stream)
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(&line.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        });
+        log(&logger, Level::Error, "fourth");
+        drop(logger);
+
+        let records = server.join().unwrap();
+        let messages: Vec<_> = records.iter().map(|r| &r["short_message"]).collect();
+        assert_eq!(messages, ["first", "second", "third", "fourth"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Accept the connection but never answer the TLS handshake.
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            let _ = done_rx.recv();
+        });
+
+        let target = TcpTarget {
+            hostname: "localhost".to_owned(),
+            port,
+            tls: true,
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert!(TcpConnection::new(&target).is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(done_tx);
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+    fn rustls_handshake() {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+        use rustls::{
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+            ServerConfig, ServerConnection, StreamOwned,
+        };
+
+        // The platform certificates are read once per process, so run the test
+        // again in a child process trusting a test certificate authority.
+        const CHILD: &str = "GELF_LOGGER_RUSTLS_TEST";
+        let Some(dir) = std::env::var_os(CHILD).map(std::path::PathBuf::from) else {
+            let dir =
+                std::env::temp_dir().join(format!("gelf_logger_rustls_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec!["localhost".to_owned()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+            std::fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+            std::fs::write(dir.join("key.pem"), key.serialize_pem()).unwrap();
+
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "logger::tests::rustls_handshake",
+                    "--test-threads=1",
+                ])
+                .env(CHILD, &dir)
+                .env("SSL_CERT_FILE", dir.join("ca.pem"))
+                .env_remove("SSL_CERT_DIR")
+                .status()
+                .unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert!(status.success());
+            return;
+        };
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from_pem_file(dir.join("cert.pem")).unwrap()],
+                PrivateKeyDer::from_pem_file(dir.join("key.pem")).unwrap(),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut line = String::new();
+            BufReader::new(StreamOwned::new(conn, stream))
+                .read_line(&mut line)
+                .unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        });
+
+        let logger = Builder::new()
+            .hostname("localhost".to_owned())
+            .port(port)
+            .tls(true)
+            .build()
+            .unwrap();
+        log(&logger, Level::Error, "encrypted");
+        logger.flush();
+        drop(logger);
+
+        let record = server.join().unwrap();
+        assert_eq!(record["short_message"], "encrypted");
+    }
+}
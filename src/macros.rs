@@ -13,42 +13,91 @@
 /// gelf_log!(GelfLevel::Informational, foo = "bar"; "Something happened");
 /// gelf_log!(target: "app-1", GelfLevel::Informational, foo = "bar"; "Something happened");
 /// ```
+///
+/// The message can be left out of event-like records made of key-values only.
+/// It is then empty, and replaced by the placeholder set with
+/// [`Builder::empty_message_placeholder`](crate::Builder::empty_message_placeholder).
+///
+/// ```
+/// use gelf_logger::{gelf_info, Builder};
+///
+/// let (builder, memory) = Builder::new()
+///     .parse_filters("info")
+///     .empty_message_placeholder(Some("event".to_owned()))
+///     .memory();
+/// builder.init();
+///
+/// gelf_info!(event = "login", user = "foo");
+///
+/// let records = memory.take();
+/// assert_eq!(records[0].short_message, "event");
+/// assert_eq!(records[0].get_str("user"), Some("foo"));
+/// ```
+///
+/// Like with the `log` macros, neither the message nor the key-values are
+/// evaluated when the level is disabled, statically through the `log`
+/// features or at runtime.
+///
+/// ```
+/// use std::cell::Cell;
+///
+/// use gelf_logger::{gelf_log, GelfLevel};
+///
+/// log::set_max_level(log::LevelFilter::Info);
+/// let calls = Cell::new(0);
+/// let expensive = || calls.set(calls.get() + 1);
+///
+/// gelf_log!(GelfLevel::Debugging, key:? = expensive(); "{:?}", expensive());
+/// assert_eq!(calls.get(), 0);
+/// gelf_log!(GelfLevel::Informational, key:? = expensive(); "{:?}", expensive());
+/// assert_eq!(calls.get(), 2);
+/// ```
 #[macro_export]
 macro_rules! gelf_log {
     // gelf_log!(target: "my_target", GelfLevel::Informational, key1:? = 42, key2 = true; "a {} event", "log");
     (target: $target:expr, $lvl:expr, $($key:tt $(:$capture:tt)? $(= $value:expr)?),+; $($arg:tt)+) => ({
-        let log_lvl = log::Level::from($lvl);
+        // The level is evaluated once, and nothing else unless it is enabled.
+        let gelf_lvl = $lvl;
+        let log_lvl = log::Level::from(gelf_lvl);
         if log_lvl <= log::STATIC_MAX_LEVEL && log_lvl <= log::max_level() {
-            let lvl_key = $crate::INTERNAL_LEVEL_FIELD_NAME;
-            let kvs = [(lvl_key, log::__log_value!(lvl_key = $lvl as u32)), $((log::__log_key!($key), log::__log_value!($key $(:$capture)* = $($value)*))),+];
-            let mut builder = log::Record::builder();
-            builder
-                .args(format_args!($($arg)+))
-                .level(log_lvl) // Will be overwrite.
-                .target($target)
-                .module_path_static(Some(module_path!()))
-                .file_static(Some(file!()))
-                .line(Some(line!()))
-                .key_values(&kvs);
-            log::logger().log(&builder.build());
+            // A single statement, for the temporaries of the message and the
+            // key-values to outlive the record.
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!($($arg)+))
+                    .level(log_lvl) // Will be overwrite.
+                    .target($target)
+                    .module_path_static(Some(module_path!()))
+                    .file_static(Some(file!()))
+                    .line(Some(line!()))
+                    .key_values(&[(log::__log_key!($crate::INTERNAL_LEVEL_FIELD_NAME), log::kv::Value::from(gelf_lvl as u32)), $((log::__log_key!($key), log::__log_value!($key $(:$capture)* = $($value)*))),+])
+                    .build(),
+            );
         }
     });
 
+    // gelf_log!(target: "my_target", GelfLevel::Informational, key1 = 42, key2 = true);
+    (target: $target:expr, $lvl:expr, $($key:tt $(:$capture:tt)? = $value:expr),+ $(,)?) => (
+        $crate::gelf_log!(target: $target, $lvl, $($key $(:$capture)? = $value),+; "")
+    );
+
     // gelf_log!(target: "my_target", GelfLevel::Informational, "a {} event", "log");
     (target: $target:expr, $lvl:expr, $($arg:tt)+) => ({
-        let log_lvl = log::Level::from($lvl);
+        // The level is evaluated once, and nothing else unless it is enabled.
+        let gelf_lvl = $lvl;
+        let log_lvl = log::Level::from(gelf_lvl);
         if log_lvl <= log::STATIC_MAX_LEVEL && log_lvl <= log::max_level() {
-            let kvs = [($crate::INTERNAL_LEVEL_FIELD_NAME, $lvl as u32)];
-            let mut builder = log::Record::builder();
-            builder
-                .args(format_args!($($arg)+))
-                .level(log_lvl) // Will be overwrite.
-                .target($target)
-                .module_path_static(Some(module_path!()))
-                .file_static(Some(file!()))
-                .line(Some(line!()))
-                .key_values(&kvs);
-            log::logger().log(&builder.build());
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!($($arg)+))
+                    .level(log_lvl) // Will be overwrite.
+                    .target($target)
+                    .module_path_static(Some(module_path!()))
+                    .file_static(Some(file!()))
+                    .line(Some(line!()))
+                    .key_values(&[($crate::INTERNAL_LEVEL_FIELD_NAME, gelf_lvl as u32)])
+                    .build(),
+            );
         }
     });
 
@@ -56,6 +105,60 @@ macro_rules! gelf_log {
     ($lvl:expr, $($arg:tt)+) => ($crate::gelf_log!(target: module_path!(), $lvl, $($arg)+));
 }
 
+/// Logs a message with the specific level and the additional fields of a
+/// pre-built [`Map`](crate::Map), or any value serializing to a map, flattened
+/// like key-values.
+///
+/// # Examples
+///
+/// ```
+/// use gelf_logger::{gelf_log_with, Builder, GelfLevel, Map, Value};
+///
+/// let (builder, memory) = Builder::new().parse_filters("info").memory();
+/// builder.init();
+///
+/// let mut fields = Map::new();
+/// fields.insert("user".to_owned(), Value::from("foo"));
+/// fields.insert("attempt".to_owned(), Value::from(3));
+/// gelf_log_with!(GelfLevel::Informational, fields; "Login failed");
+/// gelf_log_with!(target: "app-1", GelfLevel::Informational, Map::new(); "Something happened");
+///
+/// let records = memory.take();
+/// assert_eq!(records[0].get_str("user"), Some("foo"));
+/// assert_eq!(records[0].get_i64("attempt_long"), Some(3));
+/// assert_eq!(records[1].facility.as_deref(), Some("app-1"));
+/// ```
+#[macro_export]
+macro_rules! gelf_log_with {
+    // gelf_log_with!(target: "my_target", GelfLevel::Informational, fields; "a {} event", "log");
+    (target: $target:expr, $lvl:expr, $fields:expr; $($arg:tt)+) => ({
+        // The level is evaluated once, and nothing else unless it is enabled.
+        let gelf_lvl = $lvl;
+        let log_lvl = log::Level::from(gelf_lvl);
+        if log_lvl <= log::STATIC_MAX_LEVEL && log_lvl <= log::max_level() {
+            let fields = $fields;
+            let kvs = [
+                ($crate::INTERNAL_LEVEL_FIELD_NAME, log::kv::Value::from(gelf_lvl as u32)),
+                ($crate::INTERNAL_FIELDS_FIELD_NAME, log::kv::Value::from_serde(&fields)),
+            ];
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!($($arg)+))
+                    .level(log_lvl) // Will be overwrite.
+                    .target($target)
+                    .module_path_static(Some(module_path!()))
+                    .file_static(Some(file!()))
+                    .line(Some(line!()))
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+    });
+
+    // gelf_log_with!(GelfLevel::Informational, fields; "a log event")
+    ($lvl:expr, $fields:expr; $($arg:tt)+) => ($crate::gelf_log_with!(target: module_path!(), $lvl, $fields; $($arg)+));
+}
+
 /// Logs a message at the emergency level (A "panic" condition).
 ///
 /// Notify all tech staff on call? (Earthquake? Tornado?) - affects multiple
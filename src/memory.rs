@@ -0,0 +1,67 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::GelfRecord;
+
+/// A handle to the records kept by a [`Target::Memory`](crate::Target::Memory),
+/// to assert on what was logged in tests.
+///
+/// Cloning a handle is cheap: every clone shares the same records.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryHandle(Arc<Mutex<Vec<GelfRecord<'static>>>>);
+
+impl MemoryHandle {
+    /// Returns a copy of the records logged so far.
+    pub fn records(&self) -> Vec<GelfRecord<'static>> {
+        self.lock().clone()
+    }
+
+    /// Returns the records logged so far, removing them from the handle.
+    pub fn take(&self) -> Vec<GelfRecord<'static>> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub(crate) fn push(&self, record: GelfRecord<'static>) {
+        self.lock().push(record);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<GelfRecord<'static>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A bounded buffer of the most recent records, see
+/// [`Builder::keep_last`](crate::Builder::keep_last).
+#[derive(Debug)]
+pub(crate) struct Recent {
+    records: Mutex<VecDeque<GelfRecord<'static>>>,
+    capacity: usize,
+}
+
+impl Recent {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub(crate) fn push(&self, record: GelfRecord<'static>) {
+        let mut records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    pub(crate) fn records(&self) -> Vec<GelfRecord<'static>> {
+        let records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+        records.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,123 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    panic::{self, PanicHookInfo},
+};
+
+use log::{kv::ToValue, Level, Log, Record};
+
+use crate::{record::INTERNAL_FULL_MESSAGE_FIELD_NAME, GelfLevel, INTERNAL_LEVEL_FIELD_NAME};
+
+/// Install a panic hook logging panics as [`GelfLevel::Critical`] records
+/// through the global logger before calling the previously installed hook.
+///
+/// The records carry the `_panic_message` and `_panic_location` fields and, if
+/// enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`, the backtrace as
+/// `full_message`. The logger is flushed before the panic proceeds.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use gelf_logger::Builder;
+///
+/// Builder::new().init();
+/// gelf_logger::set_panic_hook();
+/// ```
+pub fn set_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log_panic(log::logger(), info);
+        previous(info);
+    }));
+}
+
+fn log_panic(logger: &dyn Log, info: &PanicHookInfo<'_>) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let location = info.location().map(ToString::to_string);
+    let backtrace = Backtrace::capture();
+    let backtrace =
+        (backtrace.status() == BacktraceStatus::Captured).then(|| backtrace.to_string());
+
+    let mut kvs = vec![
+        (
+            INTERNAL_LEVEL_FIELD_NAME,
+            (GelfLevel::Critical as u32).to_value(),
+        ),
+        ("panic_message", message.to_value()),
+    ];
+    if let Some(location) = &location {
+        kvs.push(("panic_location", location.to_value()));
+    }
+    if let Some(backtrace) = &backtrace {
+        kvs.push((INTERNAL_FULL_MESSAGE_FIELD_NAME, backtrace.to_value()));
+    }
+
+    logger.log(
+        &Record::builder()
+            .args(format_args!("{message}"))
+            .level(Level::Error)
+            .target("panic")
+            .key_values(&kvs)
+            .build(),
+    );
+    logger.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic,
+        sync::{Arc, Mutex},
+    };
+
+    use log::{Log, Metadata, Record};
+    use serde_json::Value;
+
+    use super::log_panic;
+    use crate::GelfRecord;
+
+    #[derive(Default)]
+    struct Memory(Mutex<Vec<Value>>);
+
+    impl Log for Memory {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            let record = serde_json::to_value(GelfRecord::from(record)).unwrap();
+            self.0.lock().unwrap().push(record);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn panic_hook() {
+        let memory = Arc::new(Memory::default());
+        let previous = panic::take_hook();
+        let hook_memory = memory.clone();
+        panic::set_hook(Box::new(move |info| log_panic(&*hook_memory, info)));
+        let result = panic::catch_unwind(|| panic!("something happen"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+
+        let records = memory.0.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["short_message"], "something happen");
+        assert_eq!(records[0]["level"], 2);
+        assert_eq!(records[0]["_panic_message"], "something happen");
+        assert!(records[0]["_panic_location"]
+            .as_str()
+            .unwrap()
+            .starts_with(file!()));
+    }
+}
@@ -3,8 +3,12 @@
 // Copyright 2024 The gelf_logger Authors. All rights reserved.
 
 use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    borrow::Cow,
+    io, iter,
+    num::NonZeroU32,
     sync::OnceLock,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use log::{
@@ -13,82 +17,597 @@ use log::{
 };
 use serde::Serialize;
 
-use crate::{GelfLevel, Map, Value};
+use crate::{context, non_finite, GelfLevel, LevelNameStyle, Map, Value};
 
 #[doc(hidden)]
 pub static INTERNAL_LEVEL_FIELD_NAME: &str = "__private_level";
+pub(crate) static INTERNAL_FULL_MESSAGE_FIELD_NAME: &str = "__private_full_message";
+#[doc(hidden)]
+pub static INTERNAL_FIELDS_FIELD_NAME: &str = "__private_fields";
+pub(crate) static ROUTE_FIELD_NAME: &str = "__gelf_target";
+pub(crate) static FLUSH_FIELD_NAME: &str = "__gelf_flush";
+pub(crate) static TIMESTAMP_FIELD_NAME: &str = "__gelf_timestamp";
 const GELF_VERSION: &str = "1.1";
 
 #[allow(missing_docs)]
 #[derive(Serialize, Clone, Debug)]
 pub struct GelfRecord<'a> {
     pub version: &'static str,
-    pub host: &'static str,
+    pub host: Cow<'a, str>,
     pub short_message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<u32>,
-    #[serde(rename = "_levelname")]
+    #[serde(rename = "_levelname", skip_serializing_if = "Option::is_none")]
     pub level_name: Option<&'static str>,
-    #[serde(rename = "_facility")]
-    pub facility: Option<&'a str>,
-    #[serde(rename = "_line")]
+    #[serde(rename = "_facility", skip_serializing_if = "Option::is_none")]
+    pub facility: Option<Cow<'a, str>>,
+    #[serde(rename = "_line", skip_serializing_if = "Option::is_none")]
     pub line: Option<u32>,
-    #[serde(rename = "_file")]
-    pub file: Option<&'a str>,
+    #[serde(rename = "_file", skip_serializing_if = "Option::is_none")]
+    pub file: Option<Cow<'a, str>>,
+    /// The additional fields, serialized in insertion order.
     #[serde(flatten)]
     pub additional_fields: Map<String, Value>,
 }
 
 impl<'a> GelfRecord<'a> {
+    /// Returns the additional field with the given key, with or without its
+    /// leading `_`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match key.strip_prefix('_') {
+            Some(_) => self.additional_fields.get(key),
+            None => self.additional_fields.get(&format!("_{key}")),
+        }
+    }
+
+    /// Returns the additional field with the given key if it is a string.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key)?.as_str()
+    }
+
+    /// Returns the additional field with the given key if it is an integer
+    /// fitting in an `i64`.
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get(key)?.as_i64()
+    }
+
+    /// Whether the record has an additional field with the given key.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Check the record against the GELF requirements, that Graylog would
+    /// otherwise silently drop.
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        if self.version.is_empty() {
+            return Err("missing version");
+        }
+        if self.host.is_empty() {
+            return Err("empty host");
+        }
+        if self.short_message.is_empty() {
+            return Err("empty short_message");
+        }
+        if self.additional_fields.contains_key("_id") {
+            return Err("reserved _id field");
+        }
+        let valid_name = |name: &String| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        };
+        if !self.additional_fields.keys().all(valid_name) {
+            return Err("invalid field name");
+        }
+        Ok(())
+    }
+
+    /// Serialize the record, with `simd-json` if the `simd` feature is enabled.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, io::Error> {
+        #[cfg(feature = "simd")]
+        {
+            let json = simd_json::to_vec(self).map_err(io::Error::other)?;
+            // Looking for the rare large floats is cheaper than scanning the
+            // output.
+            let rewrite = self
+                .timestamp
+                .is_some_and(|timestamp| timestamp.abs() >= MIN_EXPONENT_FLOAT)
+                || self.additional_fields.values().any(has_positive_exponent);
+            Ok(match rewrite {
+                true => sign_exponents(json),
+                false => json,
+            })
+        }
+        #[cfg(not(feature = "simd"))]
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Convert the record into one that doesn't borrow from the original
+    /// [`Record`].
+    pub fn into_owned(self) -> GelfRecord<'static> {
+        GelfRecord {
+            version: self.version,
+            host: Cow::Owned(self.host.into_owned()),
+            short_message: self.short_message,
+            full_message: self.full_message,
+            timestamp: self.timestamp,
+            level: self.level,
+            level_name: self.level_name,
+            facility: self
+                .facility
+                .map(|facility| Cow::Owned(facility.into_owned())),
+            line: self.line,
+            file: self.file.map(|file| Cow::Owned(file.into_owned())),
+            additional_fields: self.additional_fields,
+        }
+    }
+
     /// Flatten, format and add fields to the record.
     pub fn extend_additional_fields(&mut self, fields: Map<String, Value>, type_suffix: bool) {
         self.additional_fields
             .extend(flatten(fields, Some("_"), "_", type_suffix));
     }
-}
 
-/// Convert a [`Record`] into a [`GelfRecord`]. The level specified in the
-/// `Record` will be used to derive the `GelfRecord` one. If the special `kv`
-/// value inserted by the `gelf_*` macros is present and is an integer, this
-/// value will be used as `GelfLevel` instead.
-impl<'a> From<&Record<'a>> for GelfRecord<'a> {
-    fn from(record: &Record<'a>) -> Self {
-        struct Visitor(Map<String, Value>, Option<GelfLevel>);
+    /// Same as the [`From`] implementation but honoring the options set up on
+    /// the [`Builder`](crate::Builder).
+    pub(crate) fn from_record(record: &Record<'a>, options: &RecordOptions) -> Self {
+        Self::from_record_checked(record, options).0
+    }
 
-        impl<'a> VisitSource<'a> for Visitor {
+    /// Same as [`GelfRecord::from_record`], also returning the number of
+    /// fields skipped because they couldn't be serialized, along with the
+    /// last error.
+    pub(crate) fn from_record_checked(
+        record: &Record<'a>,
+        options: &RecordOptions,
+    ) -> (Self, Option<(u64, io::Error)>) {
+        struct Visitor<'o> {
+            fields: Map<String, Value>,
+            level: Option<GelfLevel>,
+            full_message: Option<String>,
+            timestamp: Option<f64>,
+            failures: Option<(u64, io::Error)>,
+            error_sources: usize,
+            options: &'o RecordOptions,
+        }
+
+        impl<'a> VisitSource<'a> for Visitor<'_> {
             fn visit_pair(
                 &mut self,
                 key: Key<'a>,
                 value: log::kv::Value<'a>,
             ) -> Result<(), KvError> {
-                let json_value = serde_json::to_value(value).map_err(KvError::boxed)?;
+                if self.options.capture_error_sources {
+                    if let Some(err) = value.to_borrowed_error() {
+                        // The causes of the following errors are numbered after
+                        // the ones of the previous errors.
+                        for cause in iter::successors(err.source(), |cause| cause.source()) {
+                            self.fields.insert(
+                                format!("error_{}", self.error_sources),
+                                Value::String(cause.to_string()),
+                            );
+                            self.error_sources += 1;
+                        }
+                    }
+                }
+
+                // The field is skipped but not the following ones.
+                let json_value = match non_finite::to_value(&value) {
+                    Ok(json_value) => json_value,
+                    Err(err) => {
+                        let count = self.failures.take().map_or(0, |(count, _)| count);
+                        self.failures = Some((count + 1, err.into()));
+                        return Ok(());
+                    }
+                };
+                let json_value = match self.options.omit_none_fields {
+                    true => match without_nulls(json_value) {
+                        Some(json_value) => json_value,
+                        None => return Ok(()),
+                    },
+                    false => json_value,
+                };
                 if key.as_str() == INTERNAL_LEVEL_FIELD_NAME && json_value.is_u64() {
-                    self.1 = Some(GelfLevel::from(json_value.as_u64().unwrap() as u32));
-                } else {
-                    self.0.insert(key.as_str().to_owned(), json_value);
+                    self.level = Some(GelfLevel::from(json_value.as_u64().unwrap() as u32));
+                } else if key.as_str() == INTERNAL_FULL_MESSAGE_FIELD_NAME {
+                    if let Value::String(full_message) = json_value {
+                        self.full_message = Some(full_message);
+                    }
+                } else if key.as_str() == TIMESTAMP_FIELD_NAME {
+                    self.timestamp = json_value.as_f64();
+                } else if key.as_str() == INTERNAL_FIELDS_FIELD_NAME && json_value.is_object() {
+                    if let Value::Object(fields) = json_value {
+                        self.fields.extend(fields);
+                    }
+                } else if key.as_str() != ROUTE_FIELD_NAME && key.as_str() != FLUSH_FIELD_NAME {
+                    match self
+                        .options
+                        .duration_unit
+                        .and_then(|unit| Some((unit, as_duration(&json_value)?)))
+                    {
+                        Some((unit, duration)) => {
+                            let (suffix, value) = unit.convert(duration);
+                            self.fields
+                                .insert(format!("{}_{suffix}", key.as_str()), value);
+                        }
+                        None => {
+                            self.fields.insert(key.as_str().to_owned(), json_value);
+                        }
+                    }
                 }
                 Ok(())
             }
         }
 
         let kvs = record.key_values();
-        let mut visitor = Visitor(Map::with_capacity(kvs.count()), None);
+        let mut visitor = Visitor {
+            fields: Map::with_capacity(kvs.count()),
+            level: None,
+            full_message: None,
+            timestamp: None,
+            failures: None,
+            error_sources: 0,
+            options,
+        };
         let _ = kvs.visit(&mut visitor);
+        let sample_bucket = context::with_trace_context(|context| {
+            let context = context?;
+            visitor
+                .fields
+                .insert("trace_id".to_owned(), Value::from(context.trace_id.clone()));
+            if let Some(span_id) = &context.span_id {
+                visitor
+                    .fields
+                    .insert("span_id".to_owned(), Value::from(span_id.clone()));
+            }
+            let buckets = options.sample_buckets?;
+            Some(context::sample_bucket(&context.trace_id, buckets))
+        });
 
-        let level = GelfLevel::from(record.level());
-        Self {
+        let level = visitor
+            .level
+            .unwrap_or_else(|| GelfLevel::from(record.level()));
+        let mut full_message = visitor.full_message;
+        if options.backtrace_level.is_some_and(|min| level <= min) {
+            let backtrace = Backtrace::capture();
+            if backtrace.status() == BacktraceStatus::Captured {
+                full_message = Some(match full_message {
+                    Some(message) => format!("{message}\n\n{backtrace}"),
+                    None => backtrace.to_string(),
+                });
+            }
+        }
+        let mut short_message = record.args().to_string();
+        if let Some(transform) = options.short_message_transform {
+            short_message = transform(&short_message);
+        }
+        if short_message.is_empty() {
+            if let Some(placeholder) = &options.empty_message_placeholder {
+                short_message.clone_from(placeholder);
+            }
+        }
+        let mut additional_fields = flatten_with(
+            visitor.fields,
+            Some("_"),
+            options.separator(),
+            true,
+            options.leaf_options(),
+        );
+        if let Some(bucket) = sample_bucket {
+            additional_fields.insert("_sample_bucket".to_owned(), Value::from(bucket));
+        }
+        let module_field_name = options.module_field_name.as_deref().unwrap_or("_module");
+        let facility = match &options.facility {
+            Some(facility) => {
+                additional_fields
+                    .insert(module_field_name.to_owned(), Value::from(record.target()));
+                Cow::Owned(facility.clone())
+            }
+            None => match options.facility_mapper {
+                Some(map) => Cow::Owned(map(record.target())),
+                None => Cow::Borrowed(record.target()),
+            },
+        };
+        if let Some(module_path) = record.module_path().filter(|_| options.emit_module_path) {
+            additional_fields.insert(module_field_name.to_owned(), Value::from(module_path));
+        }
+        let record = Self {
             version: GELF_VERSION,
-            host: hostname(),
-            short_message: record.args().to_string(),
-            timestamp: Some(now()),
-            level: Some(visitor.1.unwrap_or(level) as u32),
-            level_name: Some(<&str>::from(visitor.1.unwrap_or(level))),
-            facility: Some(record.target()),
-            line: record.line(),
-            file: record.file(),
-            additional_fields: flatten(visitor.0, Some("_"), "_", true),
+            host: match &options.host {
+                Some(host) => Cow::Owned(host.clone()),
+                None => Cow::Borrowed(hostname()),
+            },
+            short_message,
+            full_message,
+            timestamp: visitor
+                .timestamp
+                .or_else(|| (!options.omit_timestamp).then(now)),
+            level: Some(level as u32),
+            level_name: (!options.omit_level_name).then(|| level.name(options.level_name_style)),
+            facility: (!options.omit_facility).then_some(facility),
+            line: record.line().filter(|_| !options.omit_location),
+            file: record
+                .file()
+                .filter(|_| !options.omit_location)
+                .map(Cow::Borrowed),
+            additional_fields,
+        };
+        (record, visitor.failures)
+    }
+}
+
+/// The magnitude from which `simd-json` writes the floats with an exponent.
+#[cfg(feature = "simd")]
+const MIN_EXPONENT_FLOAT: f64 = 1e16;
+
+/// Whether the value holds a float written with a positive exponent, which
+/// only `serde_json` prefixes with a `+`.
+#[cfg(feature = "simd")]
+fn has_positive_exponent(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => {
+            n.is_f64() && n.as_f64().unwrap_or_default().abs() >= MIN_EXPONENT_FLOAT
+        }
+        Value::Array(array) => array.iter().any(has_positive_exponent),
+        Value::Object(object) => object.values().any(has_positive_exponent),
+        _ => false,
+    }
+}
+
+/// Write the positive exponents of the numbers like `serde_json` does, e.g.
+/// `1e+300` instead of `1e300`, for the output to be identical.
+#[cfg(feature = "simd")]
+fn sign_exponents(json: Vec<u8>) -> Vec<u8> {
+    let is_exponent = |i: usize| json[i] == b'e' && json.get(i + 1).is_some_and(u8::is_ascii_digit);
+    let mut output = Vec::with_capacity(json.len() + 8);
+    let mut start = 0;
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, &byte) in json.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if is_exponent(i) {
+            output.extend_from_slice(&json[start..=i]);
+            output.push(b'+');
+            start = i + 1;
+        }
+    }
+    output.extend_from_slice(&json[start..]);
+    output
+}
+
+/// The name of the route selected by the record, see
+/// [`Builder::route`](crate::Builder::route).
+pub(crate) fn route(record: &Record<'_>) -> Option<String> {
+    record
+        .key_values()
+        .get(Key::from_str(ROUTE_FIELD_NAME))
+        .map(|value| value.to_string())
+}
+
+/// Remove the `null` members of the objects and elements of the arrays,
+/// recursively. Returns `None` if the value itself is `null`.
+fn without_nulls(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::Object(fields) => Some(Value::Object(
+            fields
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, without_nulls(value)?)))
+                .collect(),
+        )),
+        Value::Array(values) => Some(Value::Array(
+            values.into_iter().filter_map(without_nulls).collect(),
+        )),
+        value => Some(value),
+    }
+}
+
+/// The GELF level of the record, set by the `gelf_*` macros or derived from
+/// its [`log::Level`].
+pub(crate) fn gelf_level(record: &Record<'_>) -> GelfLevel {
+    record
+        .key_values()
+        .get(Key::from_str(INTERNAL_LEVEL_FIELD_NAME))
+        .and_then(|value| value.to_u64())
+        .map_or_else(
+            || GelfLevel::from(record.level()),
+            |level| GelfLevel::from(level as u32),
+        )
+}
+
+/// Whether the record asks to be flushed right after being queued.
+pub(crate) fn flush_requested(record: &Record<'_>) -> bool {
+    record
+        .key_values()
+        .get(Key::from_str(FLUSH_FIELD_NAME))
+        .and_then(|value| value.to_bool())
+        .unwrap_or(false)
+}
+
+/// Convert a [`Record`] into a [`GelfRecord`]. The level specified in the
+/// `Record` will be used to derive the `GelfRecord` one. If the special `kv`
+/// value inserted by the `gelf_*` macros is present and is an integer, this
+/// value will be used as `GelfLevel` instead.
+impl<'a> From<&Record<'a>> for GelfRecord<'a> {
+    fn from(record: &Record<'a>) -> Self {
+        Self::from_record(record, &RecordOptions::default())
+    }
+}
+
+/// Options applied when converting a [`Record`] into a [`GelfRecord`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RecordOptions {
+    /// Walk the `source()` chain of error values and add each cause as an
+    /// `_error_<n>` field, numbered across the errors of the record.
+    pub(crate) capture_error_sources: bool,
+    /// Rewrite the rendered `short_message`.
+    pub(crate) short_message_transform: Option<fn(&str) -> String>,
+    /// Rewrite the value of each flattened field, given its key.
+    pub(crate) value_transformer: Option<fn(&str, Value) -> Value>,
+    /// How `null` field values are represented.
+    pub(crate) null_field_policy: NullFieldPolicy,
+    /// Leave out the fields set to `None`, including the members and elements
+    /// of the values captured with `:serde`.
+    pub(crate) omit_none_fields: bool,
+    /// How `NaN` and infinite field values are represented.
+    pub(crate) non_finite_float_policy: NonFiniteFloatPolicy,
+    /// Capture a backtrace into the `full_message` of the records at or above
+    /// this level.
+    pub(crate) backtrace_level: Option<GelfLevel>,
+    /// Replace empty `short_message`, that Graylog rejects.
+    pub(crate) empty_message_placeholder: Option<String>,
+    /// Replace the `_facility`, the record target being moved to the module
+    /// field.
+    pub(crate) facility: Option<String>,
+    /// Compute the `_facility` from the record target.
+    pub(crate) facility_mapper: Option<fn(&str) -> String>,
+    /// The name of the module field, `_module` if unset.
+    pub(crate) module_field_name: Option<String>,
+    /// Record the module path in the module field, independently of the
+    /// target.
+    pub(crate) emit_module_path: bool,
+    /// The casing of `_levelname`.
+    pub(crate) level_name_style: LevelNameStyle,
+    /// The `host` of every record, skipping the hostname lookup.
+    pub(crate) host: Option<String>,
+    /// Leave the `timestamp` unset so that the server assigns it on ingest.
+    pub(crate) omit_timestamp: bool,
+    /// Leave the `_facility` unset.
+    pub(crate) omit_facility: bool,
+    /// Leave the `_levelname` unset.
+    pub(crate) omit_level_name: bool,
+    /// Leave the `_file` and `_line` unset.
+    pub(crate) omit_location: bool,
+    /// Serialize integers beyond 2^53 as strings.
+    pub(crate) large_ints_as_strings: bool,
+    /// Convert the durations captured with `:serde` to numbers in this unit.
+    pub(crate) duration_unit: Option<DurationUnit>,
+    /// What to do when two values are flattened to the same field name.
+    pub(crate) collision_policy: CollisionPolicy,
+    /// Truncate the string field values longer than this many bytes.
+    pub(crate) max_field_value_len: Option<usize>,
+    /// The names of the fields, with their leading `_`, whose value is
+    /// replaced by `***`.
+    pub(crate) redacted_fields: Vec<String>,
+    /// Join the keys of nested values with `.` instead of `_`.
+    pub(crate) dotted_keys: bool,
+    /// Stamp the records with a trace context with the `_sample_bucket` of
+    /// their trace among this many.
+    pub(crate) sample_buckets: Option<NonZeroU32>,
+}
+
+impl RecordOptions {
+    /// The separator between the keys of nested values.
+    pub(crate) fn separator(&self) -> &'static str {
+        match self.dotted_keys {
+            true => ".",
+            false => "_",
         }
     }
+
+    /// How the flattened field values are rewritten.
+    pub(crate) fn leaf_options(&self) -> LeafOptions<'_> {
+        LeafOptions {
+            transform: self.value_transformer,
+            null_policy: self.null_field_policy,
+            non_finite_policy: self.non_finite_float_policy,
+            large_ints_as_strings: self.large_ints_as_strings,
+            collision_policy: self.collision_policy,
+            max_string_len: self.max_field_value_len,
+            redacted_fields: &self.redacted_fields,
+        }
+    }
+}
+
+/// What to do when two values are flattened to the same field name, e.g. the
+/// `a_b` key and the `b` field of an `a` key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Keep the last value.
+    #[default]
+    Overwrite,
+    /// Keep every value, suffixing the name of the later ones with `_1`, `_2`...
+    /// before the type suffix.
+    Disambiguate,
+}
+
+/// The unit of the [`Duration`] fields, see
+/// [`Builder::duration_unit`](crate::Builder::duration_unit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurationUnit {
+    /// A float number of seconds, with a `_s` suffix.
+    Seconds,
+    /// An integer number of milliseconds, with a `_ms` suffix.
+    Milliseconds,
+    /// An integer number of microseconds, with a `_us` suffix.
+    Microseconds,
+    /// An integer number of nanoseconds, with a `_ns` suffix.
+    Nanoseconds,
+}
+
+impl DurationUnit {
+    /// The suffix of the field name and the value of the duration.
+    fn convert(self, duration: Duration) -> (&'static str, Value) {
+        let saturate = |n: u128| Value::from(u64::try_from(n).unwrap_or(u64::MAX));
+        match self {
+            DurationUnit::Seconds => ("s", Value::from(duration.as_secs_f64())),
+            DurationUnit::Milliseconds => ("ms", saturate(duration.as_millis())),
+            DurationUnit::Microseconds => ("us", saturate(duration.as_micros())),
+            DurationUnit::Nanoseconds => ("ns", saturate(duration.as_nanos())),
+        }
+    }
+}
+
+/// The [`Duration`] serialized by serde, i.e. an object with only `secs` and
+/// `nanos` integers.
+fn as_duration(value: &Value) -> Option<Duration> {
+    let Value::Object(object) = value else {
+        return None;
+    };
+    if object.len() != 2 {
+        return None;
+    }
+    let secs = object.get("secs")?.as_u64()?;
+    let nanos = u32::try_from(object.get("nanos")?.as_u64()?).ok()?;
+    Some(Duration::new(secs, nanos))
+}
+
+/// How additional fields set to `NaN` or an infinite float are represented, as
+/// JSON has no such numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFiniteFloatPolicy {
+    /// Serialize the value as `null`, then subject to the [`NullFieldPolicy`].
+    #[default]
+    Null,
+    /// Replace the value with `"NaN"`, `"Infinity"` or `"-Infinity"`.
+    AsString,
+    /// Remove the field.
+    Drop,
+}
+
+/// How additional fields set to `null` are represented, as some Graylog
+/// configurations reject them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NullFieldPolicy {
+    /// Keep the field as `null`.
+    #[default]
+    Keep,
+    /// Remove the field.
+    Drop,
+    /// Replace the value with an empty string.
+    AsEmptyString,
 }
 
 #[inline(always)]
@@ -106,7 +625,7 @@ fn hostname() -> &'static str {
 /// Default timestamp in seconds since UNIX epoch with optional decimal places
 /// for milliseconds.
 #[inline(always)]
-fn now() -> f64 {
+pub(crate) fn now() -> f64 {
     let ts = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -120,6 +639,101 @@ pub(crate) fn flatten(
     prefix: Option<&str>,
     separator: &str,
     type_suffix: bool,
+) -> Map<String, Value> {
+    flatten_with(
+        input,
+        prefix,
+        separator,
+        type_suffix,
+        LeafOptions::default(),
+    )
+}
+
+/// How [`flatten_with`] rewrites each leaf value before the type suffix is
+/// computed.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LeafOptions<'a> {
+    /// Rewrite the value, given its key.
+    pub(crate) transform: Option<fn(&str, Value) -> Value>,
+    /// How `null` values are represented.
+    pub(crate) null_policy: NullFieldPolicy,
+    /// How the `NaN` and infinite floats, marked when serializing the
+    /// key-values, are represented.
+    pub(crate) non_finite_policy: NonFiniteFloatPolicy,
+    /// Serialize integers beyond 2^53 as strings.
+    pub(crate) large_ints_as_strings: bool,
+    /// What to do when two values are flattened to the same field name.
+    pub(crate) collision_policy: CollisionPolicy,
+    /// Truncate the strings longer than this many bytes.
+    pub(crate) max_string_len: Option<usize>,
+    /// Replace the value of the fields with these names by `***`.
+    pub(crate) redacted_fields: &'a [String],
+}
+
+/// The largest integer a double represents exactly, 2^53 - 1.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+impl LeafOptions<'_> {
+    fn apply(&self, key: &str, value: Value) -> Option<Value> {
+        if self.redacted_fields.iter().any(|name| name == key) {
+            return Some(Value::from("***"));
+        }
+        let value = match &value {
+            Value::Object(object) => match non_finite::marked_name(object) {
+                Some(name) => match self.non_finite_policy {
+                    NonFiniteFloatPolicy::Null => Value::Null,
+                    NonFiniteFloatPolicy::AsString => Value::from(name),
+                    NonFiniteFloatPolicy::Drop => return None,
+                },
+                None => value,
+            },
+            _ => value,
+        };
+        let value = match self.transform {
+            Some(transform) => transform(key, value),
+            None => value,
+        };
+        Some(match (value, self.null_policy) {
+            (Value::Null, NullFieldPolicy::Drop) => return None,
+            (Value::Null, NullFieldPolicy::AsEmptyString) => Value::String(String::new()),
+            (Value::String(mut s), _) if self.max_string_len.is_some_and(|max| s.len() > max) => {
+                // The ellipsis counts in the length, and is left out if it
+                // doesn't fit by itself.
+                let max = self.max_string_len.unwrap_or_default();
+                let ellipsis = max >= '…'.len_utf8();
+                let mut end = match ellipsis {
+                    true => max - '…'.len_utf8(),
+                    false => max,
+                };
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                s.truncate(end);
+                if ellipsis {
+                    s.push('…');
+                }
+                Value::String(s)
+            }
+            (Value::Number(n), _)
+                if self.large_ints_as_strings
+                    && (n.as_u64().is_some_and(|n| n > MAX_SAFE_INTEGER)
+                        || n.as_i64()
+                            .is_some_and(|n| n.unsigned_abs() > MAX_SAFE_INTEGER)) =>
+            {
+                Value::String(n.to_string())
+            }
+            (value, _) => value,
+        })
+    }
+}
+
+/// Same as [`flatten`] but rewriting each leaf value as set up by `leaf`.
+pub(crate) fn flatten_with(
+    input: Map<String, Value>,
+    prefix: Option<&str>,
+    separator: &str,
+    type_suffix: bool,
+    leaf: LeafOptions<'_>,
 ) -> Map<String, Value> {
     let mut path = Vec::with_capacity(8);
     if let Some(prefix) = prefix {
@@ -132,36 +746,48 @@ pub(crate) fn flatten(
         current: Value,
         separator: &str,
         type_suffix: bool,
+        leaf: &LeafOptions<'_>,
     ) {
         match current {
             Value::Array(array) => {
                 path.push(separator.to_owned());
                 for (i, v) in array.into_iter().enumerate() {
                     path.push(i.to_string());
-                    process(buffer, path, v, separator, type_suffix);
+                    process(buffer, path, v, separator, type_suffix, leaf);
                     path.pop();
                 }
                 path.pop();
             }
-            Value::Object(sub_map) => {
+            Value::Object(sub_map) if non_finite::marked_name(&sub_map).is_none() => {
                 path.push(separator.to_owned());
                 for (k, v) in sub_map {
                     path.push(k);
-                    process(buffer, path, v, separator, type_suffix);
+                    process(buffer, path, v, separator, type_suffix, leaf);
                     path.pop();
                 }
                 path.pop();
             }
             current => {
                 let mut key = path.join("");
-                if type_suffix {
-                    key += match &current {
-                        Value::Number(n) if n.is_f64() => "_float",
-                        Value::Number(_) => "_long",
-                        Value::Bool(_) => "_bool",
-                        _ => "",
-                    };
+                let Some(current) = leaf.apply(&key, current) else {
+                    return;
+                };
+                let suffix = match &current {
+                    _ if !type_suffix => "",
+                    Value::Number(n) if n.is_f64() => "_float",
+                    Value::Number(_) => "_long",
+                    Value::Bool(_) => "_bool",
+                    _ => "",
+                };
+                if leaf.collision_policy == CollisionPolicy::Disambiguate
+                    && buffer.contains_key(&format!("{key}{suffix}"))
+                {
+                    key = (1..)
+                        .map(|i| format!("{key}_{i}"))
+                        .find(|candidate| !buffer.contains_key(&format!("{candidate}{suffix}")))
+                        .unwrap();
                 }
+                key += suffix;
                 buffer.insert(key, current);
             }
         }
@@ -170,7 +796,7 @@ pub(crate) fn flatten(
     let mut buffer = Map::with_capacity(input.len());
     for (k, v) in input {
         path.push(k);
-        process(&mut buffer, &mut path, v, separator, type_suffix);
+        process(&mut buffer, &mut path, v, separator, type_suffix, &leaf);
         path.pop();
     }
 
@@ -179,10 +805,94 @@ pub(crate) fn flatten(
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        error::Error,
+        fmt,
+        num::NonZeroU32,
+        process::Command,
+        time::Duration,
+    };
+
     use log::{kv::ToValue, Level, Record};
     use serde_json::{json, Map, Value};
 
-    use super::{flatten, GelfRecord, GELF_VERSION};
+    use super::{
+        flatten, flatten_with, CollisionPolicy, DurationUnit, GelfRecord, LeafOptions,
+        NonFiniteFloatPolicy, NullFieldPolicy, RecordOptions, GELF_VERSION,
+    };
+    use crate::GelfLevel;
+
+    #[test]
+    fn to_json() {
+        let kvs = [
+            (
+                "text",
+                "quote \" backslash \\ tab \t é 🦀\u{1} 1e5".to_value(),
+            ),
+            ("float", 0.1.to_value()),
+            ("large", 1e300.to_value()),
+            ("negative", (-42).to_value()),
+            ("unsigned", u64::MAX.to_value()),
+            ("flag", true.to_value()),
+        ];
+        let record = Record::builder()
+            .args(format_args!("multi\nline"))
+            .level(Level::Error)
+            .file_static(Some(file!()))
+            .line(Some(line!()))
+            .key_values(&kvs)
+            .build();
+        let mut record = GelfRecord::from(&record);
+        record.extend_additional_fields(
+            json!({"nested": {"array": [1, "two", null], "empty": {}}})
+                .as_object()
+                .unwrap()
+                .clone(),
+            false,
+        );
+
+        assert_eq!(
+            record.to_json().unwrap(),
+            serde_json::to_vec(&record).unwrap()
+        );
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_floats() {
+        // Spread over the whole range, including around the exponent
+        // threshold.
+        let mut bits = 0x9e37_79b9_7f4a_7c15_u64;
+        let floats = std::iter::repeat_with(|| {
+            bits ^= bits << 13;
+            bits ^= bits >> 7;
+            bits ^= bits << 17;
+            f64::from_bits(bits)
+        })
+        .filter(|float| float.is_finite())
+        .take(10_000)
+        .chain((0..40).flat_map(|exp| {
+            let float = 10f64.powi(exp - 20);
+            [
+                float,
+                float.next_down(),
+                float.next_up(),
+                -float,
+                1.5 * float,
+            ]
+        }));
+        for float in floats {
+            let mut record = GelfRecord::from(&Record::builder().build());
+            record
+                .additional_fields
+                .insert("_float".to_owned(), Value::from(float));
+            assert_eq!(
+                String::from_utf8(record.to_json().unwrap()).unwrap(),
+                serde_json::to_string(&record).unwrap(),
+            );
+        }
+    }
 
     #[test]
     fn record() {
@@ -217,6 +927,660 @@ mod tests {
         );
     }
 
+    #[test]
+    fn error_sources() {
+        #[derive(Debug)]
+        struct Inner;
+
+        impl fmt::Display for Inner {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("connection refused")
+            }
+        }
+
+        impl Error for Inner {}
+
+        #[derive(Debug)]
+        struct Outer(Inner);
+
+        impl fmt::Display for Outer {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("request failed")
+            }
+        }
+
+        impl Error for Outer {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let err = Outer(Inner);
+        let kvs = [
+            ("err", log::kv::Value::from_dyn_error(&err)),
+            ("cause", log::kv::Value::from_dyn_error(&err)),
+        ];
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .key_values(&kvs)
+            .build();
+
+        let options = RecordOptions {
+            capture_error_sources: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_err"], "request failed");
+        assert_eq!(gelf_record.additional_fields["_cause"], "request failed");
+        // The causes of the second error don't overwrite the ones of the first.
+        for key in ["_error_0", "_error_1"] {
+            assert_eq!(gelf_record.additional_fields[key], "connection refused");
+        }
+        assert!(!gelf_record.additional_fields.contains_key("_error_2"));
+
+        let gelf_record = GelfRecord::from(&record);
+        assert!(!gelf_record.additional_fields.contains_key("_error_0"));
+    }
+
+    #[test]
+    fn nested_values() {
+        let ctx = HashMap::from([("k1", "a"), ("k2", "b")]);
+        let ids = json!([1, [2]]);
+        // The first one is what `ctx:serde = ctx` captures.
+        let kvs = [
+            ("ctx", log::kv::Value::from_serde(&ctx)),
+            ("ids", log::kv::Value::from_serde(&ids)),
+        ];
+        let record = Record::builder().key_values(&kvs).build();
+
+        let gelf_record = GelfRecord::from(&record);
+        assert_eq!(gelf_record.additional_fields["_ctx_k1"], "a");
+        assert_eq!(gelf_record.additional_fields["_ctx_k2"], "b");
+        assert_eq!(gelf_record.additional_fields["_ids_0_long"], 1);
+        assert_eq!(gelf_record.additional_fields["_ids_1_0_long"], 2);
+        assert!(!gelf_record.additional_fields.contains_key("_ctx"));
+    }
+
+    #[test]
+    fn short_message_transform() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+
+        let options = RecordOptions {
+            short_message_transform: Some(str::to_uppercase),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.short_message, "SOMETHING HAPPEN");
+    }
+
+    #[test]
+    fn skip_none() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .line(None)
+            .file(None)
+            .build();
+
+        let json = serde_json::to_value(GelfRecord::from(&record)).unwrap();
+        assert!(json.get("_line").is_none());
+        assert!(json.get("_file").is_none());
+    }
+
+    #[test]
+    fn omit_timestamp() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+
+        let options = RecordOptions {
+            omit_timestamp: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.timestamp, None);
+        let json = serde_json::to_value(&gelf_record).unwrap();
+        assert!(json.get("timestamp").is_none());
+    }
+
+    #[test]
+    fn duration_unit() {
+        let elapsed = Duration::from_millis(1500);
+        let kvs = [
+            ("elapsed", log::kv::Value::from_serde(&elapsed)),
+            ("other", 2.to_value()),
+        ];
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .key_values(&kvs)
+            .build();
+
+        let options = RecordOptions {
+            duration_unit: Some(DurationUnit::Milliseconds),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_elapsed_ms_long"], 1500);
+        assert_eq!(gelf_record.additional_fields["_other_long"], 2);
+
+        let options = RecordOptions {
+            duration_unit: Some(DurationUnit::Seconds),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_elapsed_s_float"], 1.5);
+    }
+
+    #[test]
+    fn timestamp_override() {
+        let kvs = [("__gelf_timestamp", 1_700_000_000.25.to_value())];
+        let record = Record::builder().key_values(&kvs).build();
+
+        let gelf_record = GelfRecord::from(&record);
+        assert_eq!(gelf_record.timestamp, Some(1_700_000_000.25));
+        assert!(!gelf_record
+            .additional_fields
+            .keys()
+            .any(|key| key.contains("gelf_timestamp")));
+    }
+
+    #[test]
+    fn omit_none_fields() {
+        #[derive(serde::Serialize)]
+        struct User {
+            id: u32,
+            email: Option<String>,
+        }
+
+        let user = User { id: 7, email: None };
+        let team = vec![
+            User { id: 8, email: None },
+            User {
+                id: 9,
+                email: Some("b@example.com".to_owned()),
+            },
+        ];
+        let kvs = [
+            ("user", log::kv::Value::from_serde(&user)),
+            ("session", log::kv::Value::from_serde(&None::<u32>)),
+            ("team", log::kv::Value::from_serde(&team)),
+        ];
+        let record = Record::builder().key_values(&kvs).build();
+
+        let gelf_record = GelfRecord::from(&record);
+        assert_eq!(gelf_record.additional_fields["_user_email"], Value::Null);
+        assert_eq!(gelf_record.additional_fields["_session"], Value::Null);
+        assert_eq!(gelf_record.additional_fields["_team_0_email"], Value::Null);
+
+        let options = RecordOptions {
+            omit_none_fields: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_user_id_long"], 7);
+        assert!(!gelf_record.additional_fields.contains_key("_user_email"));
+        assert!(!gelf_record.additional_fields.contains_key("_session"));
+        // Including the members of the structs within arrays.
+        assert_eq!(gelf_record.additional_fields["_team_0_id_long"], 8);
+        assert!(!gelf_record.additional_fields.contains_key("_team_0_email"));
+        assert_eq!(
+            gelf_record.additional_fields["_team_1_email"],
+            "b@example.com"
+        );
+    }
+
+    #[test]
+    fn collision_policy() {
+        let input = || json_to_map(json!({"a_b": 1, "a": {"b": 2}, "a_b_1": 3}));
+        assert_eq!(
+            Value::Object(flatten(input(), Some("_"), "_", true)),
+            json!({"_a_b_long": 2, "_a_b_1_long": 3})
+        );
+
+        let leaf = LeafOptions {
+            collision_policy: CollisionPolicy::Disambiguate,
+            ..Default::default()
+        };
+        assert_eq!(
+            Value::Object(flatten_with(input(), Some("_"), "_", true, leaf)),
+            json!({"_a_b_long": 1, "_a_b_1_long": 2, "_a_b_1_1_long": 3})
+        );
+    }
+
+    #[test]
+    fn dotted_keys() {
+        let value = json!({"b": "c", "d": [1]});
+        let kvs = [("a", log::kv::Value::from_serde(&value))];
+        let record = Record::builder().key_values(&kvs).build();
+        let options = RecordOptions {
+            dotted_keys: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_a.b"], "c");
+        assert_eq!(gelf_record.additional_fields["_a.d.0_long"], 1);
+    }
+
+    #[test]
+    fn max_string_len() {
+        let leaf = |max| LeafOptions {
+            max_string_len: Some(max),
+            ..Default::default()
+        };
+        let input = json_to_map(json!({
+            "body": "a".repeat(1000),
+            "accents": "ééééé",
+            "short": "ok",
+            "number": 123456789,
+        }));
+        let output = flatten_with(input.clone(), Some("_"), "_", true, leaf(8));
+        assert_eq!(
+            Value::Object(output.clone()),
+            json!({
+                "_body": "aaaaa…",
+                "_accents": "éé…",
+                "_short": "ok",
+                "_number_long": 123456789,
+            })
+        );
+        assert!(output
+            .values()
+            .filter_map(Value::as_str)
+            .all(|value| value.len() <= 8));
+
+        let output = flatten_with(input, Some("_"), "_", true, leaf(2));
+        assert_eq!(output["_body"], "aa");
+        assert_eq!(output["_accents"], "é");
+    }
+
+    #[test]
+    fn large_ints_as_strings() {
+        let leaf = LeafOptions {
+            large_ints_as_strings: true,
+            ..Default::default()
+        };
+        let input = json_to_map(json!({
+            "max": u64::MAX,
+            "min": i64::MIN,
+            "safe": (1u64 << 53) - 1,
+            "small": 42,
+        }));
+        assert_eq!(
+            Value::Object(flatten_with(input, Some("_"), "_", true, leaf)),
+            json!({
+                "_max": u64::MAX.to_string(),
+                "_min": i64::MIN.to_string(),
+                "_safe_long": (1u64 << 53) - 1,
+                "_small_long": 42,
+            })
+        );
+    }
+
+    #[test]
+    fn omit_facility() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .target("app::db")
+            .build();
+
+        let options = RecordOptions {
+            omit_facility: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.facility, None);
+        let json = serde_json::to_value(&gelf_record).unwrap();
+        assert!(json.get("_facility").is_none());
+    }
+
+    #[test]
+    fn value_transformer() {
+        let kvs = [("elapsed", 1.5.to_value()), ("other", 2.5.to_value())];
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .key_values(&kvs)
+            .build();
+
+        fn to_millis(key: &str, value: Value) -> Value {
+            match (key, value.as_f64()) {
+                ("_elapsed", Some(secs)) => Value::from((secs * 1000.0) as u64),
+                _ => value,
+            }
+        }
+        let options = RecordOptions {
+            value_transformer: Some(to_millis),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.additional_fields["_elapsed_long"], 1500);
+        assert_eq!(gelf_record.additional_fields["_other_float"], 2.5);
+    }
+
+    #[test]
+    fn backtrace() {
+        // `Backtrace::capture` reads the variables once per process, so run the
+        // test again in child processes, with backtraces enabled and disabled.
+        const CHILD: &str = "GELF_LOGGER_BACKTRACE_TEST";
+        let Some(enabled) = std::env::var_os(CHILD) else {
+            for enabled in ["1", "0"] {
+                let status = Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "record::tests::backtrace", "--test-threads=1"])
+                    .env(CHILD, enabled)
+                    .env("RUST_LIB_BACKTRACE", enabled)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+            return;
+        };
+
+        let options = RecordOptions {
+            backtrace_level: Some(GelfLevel::Error),
+            ..Default::default()
+        };
+
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .level(Level::Error)
+            .build();
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        if enabled == "1" {
+            assert!(!gelf_record.full_message.unwrap().is_empty());
+        } else {
+            assert_eq!(gelf_record.full_message, None);
+        }
+
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .level(Level::Info)
+            .build();
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.full_message, None);
+    }
+
+    #[test]
+    fn accessors() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+        let mut gelf_record = GelfRecord::from(&record);
+        gelf_record.additional_fields = json_to_map(json!({"_name": "api", "_count": 3}));
+
+        assert_eq!(gelf_record.get_str("name"), Some("api"));
+        assert_eq!(gelf_record.get_str("_name"), Some("api"));
+        assert_eq!(gelf_record.get_i64("count"), Some(3));
+        assert!(gelf_record.contains("count"));
+
+        assert_eq!(gelf_record.get_str("missing"), None);
+        assert!(!gelf_record.contains("missing"));
+
+        assert_eq!(gelf_record.get_str("count"), None);
+        assert_eq!(gelf_record.get_i64("name"), None);
+    }
+
+    #[test]
+    fn field_order() {
+        let kvs = [
+            ("zeta", 1.to_value()),
+            ("alpha", 2.to_value()),
+            ("mu", 3.to_value()),
+        ];
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .key_values(&kvs)
+            .build();
+        let gelf_record = GelfRecord::from(&record);
+
+        let json = serde_json::to_string(&gelf_record).unwrap();
+        let positions: Vec<_> = ["_zeta", "_alpha", "_mu"]
+            .iter()
+            .map(|name| json.find(name).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn validate() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+        let mut gelf_record = GelfRecord::from(&record);
+        assert_eq!(gelf_record.validate(), Ok(()));
+
+        gelf_record
+            .additional_fields
+            .insert("_id".to_owned(), Value::from(1));
+        assert_eq!(gelf_record.validate(), Err("reserved _id field"));
+
+        gelf_record.additional_fields.clear();
+        gelf_record
+            .additional_fields
+            .insert("_a b".to_owned(), Value::from(1));
+        assert_eq!(gelf_record.validate(), Err("invalid field name"));
+    }
+
+    #[test]
+    fn empty_message_placeholder() {
+        let options = RecordOptions {
+            empty_message_placeholder: Some("(empty)".to_owned()),
+            ..Default::default()
+        };
+
+        let record = Record::builder().args(format_args!("")).build();
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.short_message, "(empty)");
+
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.short_message, "something happen");
+    }
+
+    #[test]
+    fn host() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+
+        let options = RecordOptions {
+            host: Some("api-1".to_owned()),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.host, "api-1");
+    }
+
+    #[test]
+    fn facility() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .target("app::db")
+            .build();
+
+        let gelf_record = GelfRecord::from(&record);
+        assert_eq!(gelf_record.facility.as_deref(), Some("app::db"));
+        assert!(!gelf_record.additional_fields.contains_key("_module"));
+
+        let options = RecordOptions {
+            facility: Some("billing".to_owned()),
+            module_field_name: Some("_logger".to_owned()),
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.facility.as_deref(), Some("billing"));
+        assert_eq!(gelf_record.additional_fields["_logger"], "app::db");
+    }
+
+    #[test]
+    fn facility_mapper() {
+        let options = RecordOptions {
+            facility_mapper: Some(|target| match target.split("::").next() {
+                Some("db") => "database".to_owned(),
+                _ => target.to_owned(),
+            }),
+            ..Default::default()
+        };
+        for (target, facility) in [("db::pool", "database"), ("api", "api")] {
+            let record = Record::builder().target(target).build();
+            let gelf_record = GelfRecord::from_record(&record, &options);
+            assert_eq!(gelf_record.facility.as_deref(), Some(facility));
+        }
+    }
+
+    #[test]
+    fn module_path() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .target("svc")
+            .module_path_static(Some("app::db"))
+            .build();
+
+        let gelf_record = GelfRecord::from(&record);
+        assert!(!gelf_record.additional_fields.contains_key("_module"));
+
+        let options = RecordOptions {
+            emit_module_path: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.facility.as_deref(), Some("svc"));
+        assert_eq!(gelf_record.additional_fields["_module"], "app::db");
+
+        let options = RecordOptions {
+            facility: Some("billing".to_owned()),
+            emit_module_path: true,
+            ..Default::default()
+        };
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert_eq!(gelf_record.facility.as_deref(), Some("billing"));
+        assert_eq!(gelf_record.additional_fields["_module"], "app::db");
+    }
+
+    #[test]
+    fn trace_context() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+
+        {
+            let _guard = crate::set_trace_context("abc".to_owned(), Some("def".to_owned()));
+            let gelf_record = GelfRecord::from(&record);
+            assert_eq!(gelf_record.additional_fields["_trace_id"], "abc");
+            assert_eq!(gelf_record.additional_fields["_span_id"], "def");
+        }
+
+        let gelf_record = GelfRecord::from(&record);
+        assert!(!gelf_record.additional_fields.contains_key("_trace_id"));
+        assert!(!gelf_record.additional_fields.contains_key("_span_id"));
+    }
+
+    #[test]
+    fn sample_bucket() {
+        let record = Record::builder()
+            .args(format_args!("something happen"))
+            .build();
+        let options = RecordOptions {
+            sample_buckets: NonZeroU32::new(16),
+            ..Default::default()
+        };
+        let bucket = |trace_id: &str| {
+            let _guard = crate::set_trace_context(trace_id.to_owned(), None);
+            GelfRecord::from_record(&record, &options).additional_fields["_sample_bucket"]
+                .as_u64()
+                .unwrap()
+        };
+
+        let first = bucket("4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(first < 16);
+        assert_eq!(bucket("4bf92f3577b34da6a3ce929d0e0e4736"), first);
+        let buckets: HashSet<_> = (0..100).map(|i| bucket(&i.to_string())).collect();
+        assert!(buckets.len() > 1);
+
+        let gelf_record = GelfRecord::from_record(&record, &options);
+        assert!(!gelf_record.contains("sample_bucket"));
+    }
+
+    #[test]
+    fn non_finite_float_policy() {
+        #[derive(serde::Serialize)]
+        struct Metrics {
+            ratio: f64,
+            samples: Vec<f32>,
+        }
+
+        let metrics = Metrics {
+            ratio: f64::NAN,
+            samples: vec![f32::INFINITY],
+        };
+        let kvs = [
+            ("nan", f64::NAN.to_value()),
+            ("inf", f64::NEG_INFINITY.to_value()),
+            ("finite", 1.5.to_value()),
+            ("metrics", log::kv::Value::from_serde(&metrics)),
+        ];
+        let record = Record::builder().key_values(&kvs).build();
+        let fields = |policy| {
+            let options = RecordOptions {
+                non_finite_float_policy: policy,
+                ..Default::default()
+            };
+            let fields = GelfRecord::from_record(&record, &options).additional_fields;
+            [
+                "_nan",
+                "_inf",
+                "_finite_float",
+                "_metrics_ratio",
+                "_metrics_samples_0",
+            ]
+            .map(|key| fields.get(key).cloned())
+        };
+
+        assert_eq!(
+            fields(NonFiniteFloatPolicy::Null),
+            [
+                Some(json!(null)),
+                Some(json!(null)),
+                Some(json!(1.5)),
+                Some(json!(null)),
+                Some(json!(null))
+            ]
+        );
+        assert_eq!(
+            fields(NonFiniteFloatPolicy::AsString),
+            [
+                Some(json!("NaN")),
+                Some(json!("-Infinity")),
+                Some(json!(1.5)),
+                Some(json!("NaN")),
+                Some(json!("Infinity"))
+            ]
+        );
+        assert_eq!(
+            fields(NonFiniteFloatPolicy::Drop),
+            [None, None, Some(json!(1.5)), None, None]
+        );
+    }
+
+    #[test]
+    fn null_field_policy() {
+        let flatten_null = |policy| {
+            let input = json_to_map(json!({"foo": null}));
+            let leaf = LeafOptions {
+                null_policy: policy,
+                ..Default::default()
+            };
+            Value::Object(flatten_with(input, Some("_"), "_", true, leaf))
+        };
+        assert_eq!(flatten_null(NullFieldPolicy::Keep), json!({"_foo": null}));
+        assert_eq!(flatten_null(NullFieldPolicy::Drop), json!({}));
+        assert_eq!(
+            flatten_null(NullFieldPolicy::AsEmptyString),
+            json!({"_foo": ""})
+        );
+    }
+
     fn json_to_map(value: Value) -> Map<String, Value> {
         match value {
             Value::Object(map) => map,
@@ -309,6 +1673,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn type_suffix() {
         assert_eq!(
             flatten(
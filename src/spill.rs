@@ -0,0 +1,98 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+/// A file used to store the records that couldn't be queued or sent, to
+/// replay them once the connection is back.
+#[derive(Clone, Debug)]
+pub struct SpillFile {
+    /// The path of the file. Records left in it are replayed on startup.
+    pub path: PathBuf,
+    /// The maximum size of the file. Once reached, new records are dropped.
+    pub max_bytes: u64,
+}
+
+/// An append-only queue of records backed by a [`SpillFile`]. Each record is
+/// stored prefixed by its length as a little-endian `u32`.
+#[derive(Debug)]
+pub(crate) struct Spill {
+    state: Mutex<State>,
+    max_bytes: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    file: File,
+    len: u64,
+}
+
+impl Spill {
+    pub(crate) fn open(config: &SpillFile) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&config.path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            state: Mutex::new(State { file, len }),
+            max_bytes: config.max_bytes,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().len == 0
+    }
+
+    /// Append a record. Returns `false` if it was dropped because the file is
+    /// full or can't be written.
+    pub(crate) fn push(&self, data: &[u8]) -> bool {
+        let mut state = self.lock();
+        let size = 4 + data.len() as u64;
+        if state.len + size > self.max_bytes {
+            return false;
+        }
+
+        let mut buffer = Vec::with_capacity(size as usize);
+        buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(data);
+        if state.file.write_all(&buffer).is_err() {
+            return false;
+        }
+        state.len += size;
+        true
+    }
+
+    /// Take every stored record out of the file.
+    pub(crate) fn drain(&self) -> io::Result<Vec<Vec<u8>>> {
+        let mut state = self.lock();
+        let mut content = Vec::with_capacity(state.len as usize);
+        state.file.seek(SeekFrom::Start(0))?;
+        state.file.read_to_end(&mut content)?;
+        state.file.set_len(0)?;
+        state.len = 0;
+
+        let mut records = Vec::new();
+        let mut rest = content.as_slice();
+        while rest.len() >= 4 {
+            let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let Some(record) = rest.get(4..4 + len) else {
+                break;
+            };
+            records.push(record.to_vec());
+            rest = &rest[4 + len..];
+        }
+        Ok(records)
+    }
+}
@@ -0,0 +1,170 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// A snapshot of the counters of a [`GelfLogger`](crate::GelfLogger).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of records dropped because they failed the strict mode
+    /// validation.
+    pub invalid: u64,
+    /// The number of records dropped because they were older than the
+    /// maximum record age when dequeued.
+    pub stale: u64,
+    /// The number of records dropped because the background buffer was full.
+    pub dropped: u64,
+    /// The number of records written to the TCP connection.
+    pub sent: u64,
+    /// The number of failed attempts to connect, write or flush.
+    pub errors: u64,
+    /// The number of fields skipped and records dropped because they couldn't
+    /// be serialized.
+    pub serialization_errors: u64,
+}
+
+/// The counters shared by every clone of a logger.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) invalid: AtomicU64,
+    pub(crate) stale: AtomicU64,
+    pub(crate) dropped: AtomicU64,
+    /// The records dropped since the last summary record.
+    pub(crate) unreported_drops: AtomicU64,
+    /// When the last summary record was sent, in milliseconds since the UNIX
+    /// epoch.
+    pub(crate) last_drop_report: AtomicU64,
+    pub(crate) sent: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) serialization_errors: AtomicU64,
+    /// Whether the last connection operation failed.
+    pub(crate) down: AtomicBool,
+}
+
+impl Counters {
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            invalid: self.invalid.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            serialization_errors: self.serialization_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count the outcome of a connection operation.
+    pub(crate) fn report(&self, ok: bool) {
+        if !ok {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.down.store(!ok, Ordering::Relaxed);
+    }
+
+    /// Render the counters in the Prometheus text exposition format.
+    #[cfg(feature = "prometheus")]
+    pub(crate) fn prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let stats = self.snapshot();
+        let up = u64::from(!self.down.load(Ordering::Relaxed));
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+            let _ = writeln!(output, "# HELP gelf_logger_{name} {help}");
+            let _ = writeln!(output, "# TYPE gelf_logger_{name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(output, "gelf_logger_{name}{labels} {value}");
+            }
+        };
+        metric(
+            "sent_total",
+            "counter",
+            "Records written to the connection.",
+            &[("", stats.sent)],
+        );
+        metric(
+            "dropped_total",
+            "counter",
+            "Records dropped before being sent.",
+            &[
+                ("{reason=\"buffer_full\"}", stats.dropped),
+                ("{reason=\"stale\"}", stats.stale),
+                ("{reason=\"invalid\"}", stats.invalid),
+            ],
+        );
+        metric(
+            "errors_total",
+            "counter",
+            "Failed attempts to connect, write or flush.",
+            &[("", stats.errors)],
+        );
+        metric(
+            "serialization_errors_total",
+            "counter",
+            "Fields or records that couldn't be serialized.",
+            &[("", stats.serialization_errors)],
+        );
+        metric(
+            "up",
+            "gauge",
+            "Whether the last connection operation succeeded.",
+            &[("", up)],
+        );
+        output
+    }
+
+    /// Count a record written to the connection.
+    pub(crate) fn report_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.report(true);
+    }
+}
+
+#[cfg(all(test, feature = "prometheus"))]
+mod tests {
+    use std::{collections::HashSet, sync::atomic::Ordering};
+
+    use super::Counters;
+
+    #[test]
+    fn prometheus() {
+        let counters = Counters::default();
+        counters.report_sent();
+        counters.report_sent();
+        counters.dropped.fetch_add(3, Ordering::Relaxed);
+        counters.report(false);
+        let output = counters.prometheus();
+
+        let mut names = HashSet::new();
+        for line in output.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                assert!(matches!(parts.next(), Some("HELP" | "TYPE")));
+                names.insert(parts.next().unwrap());
+                assert!(!parts.next().unwrap().is_empty());
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            value.parse::<f64>().unwrap();
+            let name = series.split('{').next().unwrap();
+            assert!(names.contains(name), "{name} has no HELP/TYPE lines");
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        }
+        assert_eq!(
+            names,
+            HashSet::from([
+                "gelf_logger_sent_total",
+                "gelf_logger_dropped_total",
+                "gelf_logger_errors_total",
+                "gelf_logger_serialization_errors_total",
+                "gelf_logger_up",
+            ])
+        );
+        assert!(output.contains("gelf_logger_sent_total 2\n"));
+        assert!(output.contains("gelf_logger_dropped_total{reason=\"buffer_full\"} 3\n"));
+        assert!(output.contains("gelf_logger_errors_total 1\n"));
+        assert!(output.contains("gelf_logger_up 0\n"));
+    }
+}
@@ -0,0 +1,57 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2024 The gelf_logger Authors. All rights reserved.
+
+// TLS backends. When both the `native-tls` and `rustls` features are enabled,
+// `native-tls` takes precedence.
+
+use std::net::TcpStream;
+
+use crate::Error;
+
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub(crate) type TlsStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+#[cfg(feature = "native-tls")]
+pub(crate) type TlsStream = native_tls::TlsStream<TcpStream>;
+
+/// The `rustls` client configuration, trusting the platform certificates.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub(crate) fn rustls_config() -> std::sync::Arc<rustls::ClientConfig> {
+    use std::sync::{Arc, OnceLock};
+
+    use rustls::{ClientConfig, RootCertStore};
+
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+/// Perform the TLS handshake over an established TCP stream.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub(crate) fn connect(hostname: &str, mut stream: TcpStream) -> Result<TlsStream, Error> {
+    use rustls::{pki_types::ServerName, ClientConnection};
+
+    let server_name = ServerName::try_from(hostname.to_owned())?;
+    let mut conn = ClientConnection::new(rustls_config(), server_name)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)?;
+    }
+    Ok(rustls::StreamOwned::new(conn, stream))
+}
+
+/// Perform the TLS handshake over an established TCP stream.
+#[cfg(feature = "native-tls")]
+pub(crate) fn connect(hostname: &str, stream: TcpStream) -> Result<TlsStream, Error> {
+    let connector = native_tls::TlsConnector::new()?;
+    Ok(connector.connect(hostname, stream)?)
+}